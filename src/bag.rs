@@ -1,6 +1,142 @@
-use crate::piece::Piece;
+use crate::piece::{PieceType, PIECE_TYPES};
+use crate::randomizer::Randomizer;
 
+// Every piece num_rep times, shuffled and dealt out before the next bag is
+// filled. data is what's left of the bag being dealt.
 pub struct Bag {
-    data: Vec<Piece>,
-    num_rep: u16
-}
\ No newline at end of file
+    data: Vec<PieceType>,
+    num_rep: u16,
+}
+
+impl Bag {
+    pub fn new(num_rep: u16) -> Bag {
+        Bag {
+            data: Bag::full(num_rep),
+            num_rep,
+        }
+    }
+
+    // partway through a bag, with these pieces already dealt from it
+    pub fn with_used(num_rep: u16, used: &[PieceType]) -> Result<Bag, String> {
+        let mut bag = Bag::new(num_rep);
+        for piece_type in used.iter() {
+            match bag.data.iter().position(|p| p == piece_type) {
+                Some(i) => bag.data.remove(i),
+                None => {
+                    return Err(format!(
+                        "the bag doesn't have that many {} pieces to use",
+                        piece_type
+                    ))
+                }
+            };
+        }
+        Ok(bag)
+    }
+
+    // how many pieces of a queue this long come out of the next bag
+    pub fn drawn_from_next(&self, length: usize) -> usize {
+        length.saturating_sub(self.data.len())
+    }
+
+    // How many extra pieces a queue of length pieces can have on top when
+    // only next_bag pieces may come from the next bag. Extra pieces past
+    // that never get drawn.
+    pub fn extras_within(
+        &self,
+        length: usize,
+        extra_pieces: usize,
+        next_bag: usize,
+    ) -> Result<usize, String> {
+        let drawn = self.drawn_from_next(length);
+        if drawn > next_bag {
+            return Err(format!(
+                "the setup draws {} pieces from the next bag, more than the {} allowed",
+                drawn, next_bag
+            ));
+        }
+        Ok(extra_pieces.min(self.data.len() + next_bag - length))
+    }
+
+    // every distinct queue this long the bag can deal
+    pub fn deals(&self, length: usize) -> Vec<Vec<PieceType>> {
+        let mut deals = Vec::new();
+        self.push_deals(self.data.clone(), length, &mut Vec::new(), &mut deals);
+        deals
+    }
+
+    fn push_deals(
+        &self,
+        left: Vec<PieceType>,
+        length: usize,
+        queue: &mut Vec<PieceType>,
+        deals: &mut Vec<Vec<PieceType>>,
+    ) {
+        if queue.len() == length {
+            deals.push(queue.clone());
+            return;
+        }
+        let left = if left.is_empty() {
+            Bag::full(self.num_rep)
+        } else {
+            left
+        };
+
+        let mut types = left.clone();
+        types.dedup();
+        for piece_type in types.into_iter() {
+            let mut next = left.clone();
+            let i = next.iter().position(|p| *p == piece_type).unwrap();
+            next.remove(i);
+            queue.push(piece_type);
+            self.push_deals(next, length, queue, deals);
+            queue.pop();
+        }
+    }
+
+    // what's left of the bag being dealt once the queue is dealt, nothing
+    // when it ends right where a bag does
+    pub fn after(&self, queue: &[PieceType]) -> Vec<PieceType> {
+        let mut left = self.data.clone();
+        for piece_type in queue.iter() {
+            if left.is_empty() {
+                left = Bag::full(self.num_rep);
+            }
+            if let Some(i) = left.iter().position(|p| p == piece_type) {
+                left.remove(i);
+            }
+        }
+        left
+    }
+
+    fn full(num_rep: u16) -> Vec<PieceType> {
+        PIECE_TYPES
+            .iter()
+            .flat_map(|piece_type| std::iter::repeat_n(*piece_type, num_rep as usize))
+            .collect()
+    }
+}
+
+impl Randomizer for Bag {
+    fn queue_chance(&self, queue: &[PieceType]) -> f64 {
+        let mut left = self.data.clone();
+        let mut chance = 1.0;
+
+        for piece_type in queue.iter() {
+            if left.is_empty() {
+                left = Bag::full(self.num_rep);
+            }
+            let i = match left.iter().position(|p| p == piece_type) {
+                Some(i) => i,
+                None => return 0.0,
+            };
+            let count = left.iter().filter(|p| *p == piece_type).count();
+            chance *= count as f64 / left.len() as f64;
+            left.remove(i);
+        }
+        chance
+    }
+
+    fn bag_repeats(&self) -> Option<u16> {
+        Some(self.num_rep)
+    }
+}
@@ -1,71 +1,685 @@
 use crate::fieldmatrix::FieldMatrix;
+use crate::hold_comp::{implied_by, HoldRules};
 use crate::perm_gen::generate_perm_iter;
-use crate::piece::{piece_can_be_placed, place_piece_on_field, Piece};
+use crate::piece::dependency::PlacementGraph;
+use crate::piece::finesse::finesse;
+use crate::piece::gravity::Gravity;
+use crate::piece::lock_delay::LockDelay;
+use crate::piece::movement::{placement_spin, Spin};
+use crate::piece::rotation_system::RotationSystem;
+use crate::piece::{
+    fumen_index_to_piece_type, piece_block_positions, piece_can_be_placed, piece_in_region,
+    piece_type_to_fumen_index, place_piece_on_field, placements_in_region, Piece, PieceType,
+    Placement, PIECE_TYPES,
+};
+use crate::randomizer::Randomizer;
+use std::collections::HashMap;
+use std::thread;
 
-pub const BOTTOM_ROW_DISCARD_COUNT: usize = 1;
+// the lowest row of the field. Everything is moved down so the floor is
+// always directly under this row, whether the garbage row is kept or not.
+pub const FLOOR_ROW: usize = 23;
 
 #[derive(Copy, Clone)]
-pub struct PercentageOptions {
+pub struct PercentageOptions<'a> {
     pub hold: bool,
     pub spin: bool,
     pub soft_drop: bool,
+    pub rotation_system: RotationSystem<'a>,
+    // only used for the finesse inputs
+    pub das: bool,
+    pub lock_delay: LockDelay,
+    pub gravity: Gravity,
+    // the most inputs a piece can take to get into place, the hard drop
+    // aside
+    pub max_inputs: Option<usize>,
+    // weights the queues by how often it deals them, every queue counts
+    // the same without one
+    pub randomizer: Option<&'a dyn Randomizer>,
+    // the piece in hold before the queue starts
+    pub initial_hold: Option<PieceType>,
+    // the first pieces of the queue, current piece first
+    pub known_queue: &'a [PieceType],
+    pub hold_rules: HoldRules<PieceType>,
+    // how many more pieces the queue has than the setup needs, and whether
+    // they can be placed out of the setup's way instead of held or left
+    pub extra_pieces: usize,
+    pub place_extras: bool,
+    // how many threads the queues are split between
+    pub threads: usize,
 }
 
-impl PercentageOptions {
+impl<'a> PercentageOptions<'a> {
     pub fn new(hold: bool, soft_drop: bool, spin: bool) -> Self {
         PercentageOptions {
             hold,
             spin,
             soft_drop,
+            rotation_system: RotationSystem::Srs,
+            das: true,
+            lock_delay: LockDelay::Infinite,
+            gravity: Gravity::Normal,
+            max_inputs: None,
+            randomizer: None,
+            initial_hold: None,
+            known_queue: &[],
+            hold_rules: HoldRules::default(),
+            extra_pieces: 0,
+            place_extras: false,
+            threads: 1,
         }
     }
 }
 
 pub fn find_percentage(
     base_field: FieldMatrix,
-    pieces: Vec<Piece>,
+    setups: Vec<Vec<Piece>>,
+    dont_care: &FieldMatrix,
     options: PercentageOptions,
 ) -> f64 {
-    let mut perm_pieces = pieces.clone();
-    let permutations = generate_perm_iter(&mut perm_pieces);
-    let mut permutation_count: u64 = 0;
-
-    let works_perms_no_hold = permutations
-        .filter(|perm| {
-            permutation_count += 1;
-            permutation_works(&base_field, &perm, options)
+    percentage(
+        &find_working_queues(base_field, setups, dont_care, options),
+        options,
+    )
+}
+
+// With a randomizer every queue counts as often as it deals it, given that
+// it deals the setup's pieces at all.
+pub fn percentage(queues: &[(Vec<PieceType>, bool)], options: PercentageOptions) -> f64 {
+    let chance = |queue: &[PieceType]| match options.randomizer {
+        Some(randomizer) => randomizer.queue_chance(queue),
+        None => 1.0,
+    };
+    let total = queues.iter().map(|(queue, _)| chance(queue)).sum::<f64>();
+    // folded from 0.0, an empty sum would be -0.0
+    let works = queues
+        .iter()
+        .filter(|(_, works)| *works)
+        .fold(0.0, |works, (queue, _)| works + chance(queue));
+    // the randomizer might never deal these pieces
+    if total == 0.0 {
+        return 0.0;
+    }
+    works / total * 100.0
+}
+
+// The queues split by their first few pieces, current piece first. For each
+// prefix: the percentage of the queues starting with it that work, and how
+// likely a queue is to start with it at all. Best prefixes first, leaving
+// out the ones the randomizer never deals.
+pub fn prefix_percentages(
+    queues: &[(Vec<PieceType>, bool)],
+    length: usize,
+    options: PercentageOptions,
+) -> Vec<(Vec<PieceType>, f64, f64)> {
+    let mut by_prefix = HashMap::new();
+    for (queue, works) in queues.iter() {
+        let prefix = queue[..length.min(queue.len())].to_vec();
+        by_prefix
+            .entry(prefix)
+            .or_insert_with(Vec::new)
+            .push((queue.clone(), *works));
+    }
+
+    let chance = |queues: &[(Vec<PieceType>, bool)]| match options.randomizer {
+        Some(randomizer) => deal_chance(queues, randomizer),
+        None => queues.len() as f64,
+    };
+    let total = chance(queues);
+    let mut table = by_prefix
+        .into_iter()
+        .filter(|(_, with_prefix)| chance(with_prefix) > 0.0)
+        .map(|(prefix, with_prefix)| {
+            let share = chance(&with_prefix) / total * 100.0;
+            (prefix, percentage(&with_prefix, options), share)
+        })
+        .collect::<Vec<(Vec<PieceType>, f64, f64)>>();
+    table.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+    table
+}
+
+// how likely the randomizer is to deal these pieces in any order
+pub fn deal_chance(queues: &[(Vec<PieceType>, bool)], randomizer: &dyn Randomizer) -> f64 {
+    queues
+        .iter()
+        .map(|(queue, _)| randomizer.queue_chance(queue))
+        .sum::<f64>()
+        * 100.0
+}
+
+// All the setups have to be made of the same pieces (see group_setups). A
+// queue works if it can build any one of them. Pieces of the same type are
+// interchangeable, so only distinct queues are listed.
+//
+// pieces lying completely inside the dont_care cells are not tied to the spot
+// they are drawn in: they can go anywhere inside the dont_care cells.
+//
+// The held piece and the known pieces are fixed, only the rest of the queue
+// is shuffled. Extra pieces of every type get shuffled in too, and the setup
+// can leave them in hold, not draw them or, with place_extras, place them
+// beside or above the setup's pieces.
+pub fn find_working_queues(
+    base_field: FieldMatrix,
+    setups: Vec<Vec<Piece>>,
+    dont_care: &FieldMatrix,
+    options: PercentageOptions,
+) -> Vec<(Vec<PieceType>, bool)> {
+    let queue_pieces = match unknown_pieces(&setups[0], options) {
+        Ok(queue_pieces) => queue_pieces,
+        Err(_) => return Vec::new(),
+    };
+
+    let queues = extra_piece_sets(options.extra_pieces)
+        .into_iter()
+        .flat_map(|extras| {
+            let mut pieces = queue_pieces.clone();
+            pieces.extend(extras);
+            generate_perm_iter(pieces)
+        })
+        .map(|rest| {
+            let mut queue = options.known_queue.to_vec();
+            queue.extend(rest);
+            queue
+        })
+        .collect::<Vec<Vec<PieceType>>>();
+
+    // The queues come in order, so each thread gets the queues starting
+    // with the same few pieces, which share most of their orders. They're
+    // put back together in the same order.
+    let threads = options.threads.clamp(1, queues.len().max(1));
+    let chunk_size = queues.len().div_ceil(threads).max(1);
+    let check_chunk = |chunk: &[Vec<PieceType>]| {
+        let mut queue_works = queue_checker(base_field, &setups, dont_care, options);
+        chunk
+            .iter()
+            .map(|queue| (queue.clone(), queue_works(queue)))
+            .collect::<Vec<(Vec<PieceType>, bool)>>()
+    };
+    if threads == 1 {
+        return check_chunk(&queues);
+    }
+    thread::scope(|scope| {
+        let handles = queues
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || check_chunk(chunk)))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+// Tells whether a queue builds any of the setups, the known pieces included.
+pub fn queue_checker<'a>(
+    base_field: FieldMatrix,
+    setups: &[Vec<Piece>],
+    dont_care: &'a FieldMatrix,
+    options: PercentageOptions<'a>,
+) -> impl FnMut(&[PieceType]) -> bool + 'a {
+    let mut builders = setups
+        .iter()
+        .map(|setup| {
+            let graph = PlacementGraph::new(setup, &base_field, dont_care, options);
+            SetupBuilder::new(graph, dont_care, options)
         })
-        .collect::<Vec<Vec<Piece>>>();
+        .collect::<Vec<SetupBuilder>>();
 
-    let works_count = if options.hold {
-        crate::hold_comp::compute_with_hold(pieces, works_perms_no_hold)
+    // with hold, the same orders get tested for many queues
+    let mut order_cache: HashMap<Vec<PieceType>, bool> = HashMap::new();
+    let mut order_works = move |order: &[PieceType]| -> bool {
+        if let Some(works) = order_cache.get(order) {
+            return *works;
+        }
+
+        let works = builders
+            .iter_mut()
+            .any(|builder| builder.builds(&base_field, order));
+        order_cache.insert(order.to_vec(), works);
+        works
+    };
+
+    let length = setups[0].len();
+    move |queue: &[PieceType]| {
+        placement_orders(queue, length, options)
+            .iter()
+            .any(|order| order_works(order))
+    }
+}
+
+// the orders the queue's pieces can be placed in using hold
+fn placement_orders(
+    queue: &[PieceType],
+    length: usize,
+    options: PercentageOptions,
+) -> Vec<Vec<PieceType>> {
+    let hold_rules = if options.hold && options.gravity.allows_hold() {
+        options.hold_rules
     } else {
-        works_perms_no_hold.len() as u64
+        HoldRules {
+            max_uses: Some(0),
+            ..HoldRules::default()
+        }
     };
+    implied_by(
+        queue,
+        options.initial_hold,
+        length,
+        options.place_extras,
+        &hold_rules,
+    )
+}
+
+// How this queue builds one of the setups: which setup, and each piece in
+// the order it gets placed with how it's placed and the inputs that place
+// it. Extra pieces placed out of the way are in there too.
+pub fn trace_queue(
+    base_field: FieldMatrix,
+    setups: &[Vec<Piece>],
+    dont_care: &FieldMatrix,
+    options: PercentageOptions,
+    queue: &[PieceType],
+) -> Option<(usize, Vec<Placement>)> {
+    let mut builders = setups
+        .iter()
+        .map(|setup| {
+            let graph = PlacementGraph::new(setup, &base_field, dont_care, options);
+            SetupBuilder::tracing(graph, dont_care, options)
+        })
+        .collect::<Vec<SetupBuilder>>();
+
+    for order in placement_orders(queue, setups[0].len(), options).iter() {
+        for (i, builder) in builders.iter_mut().enumerate() {
+            let pieces = match builder.trace(&base_field, order) {
+                Some(pieces) => pieces,
+                None => continue,
+            };
+            let mut field = base_field;
+            let placements = pieces
+                .into_iter()
+                .map(|piece| {
+                    let placement = placement(piece, &field, options);
+                    place_piece_on_field(piece, &mut field);
+                    placement
+                })
+                .collect();
+            return Some((i, placements));
+        }
+    }
+    None
+}
 
-    works_count as f64 / permutation_count as f64 * 100.0
+// every set of this many pieces that could come on top of the setup's
+fn extra_piece_sets(count: usize) -> Vec<Vec<PieceType>> {
+    let mut sets = vec![Vec::new()];
+    for _ in 0..count {
+        sets = sets
+            .into_iter()
+            .flat_map(|set: Vec<PieceType>| {
+                // kept in the order of PIECE_TYPES so each set comes up once
+                let from = set.last().map_or(0, |last| {
+                    PIECE_TYPES.iter().position(|p| p == last).unwrap()
+                });
+                PIECE_TYPES[from..].iter().map(move |piece_type| {
+                    let mut set = set.clone();
+                    set.push(*piece_type);
+                    set
+                })
+            })
+            .collect();
+    }
+    sets
 }
 
-fn permutation_works(
+// The setup's pieces that aren't in hold or known to be coming first, along
+// with the piece that has to be left in hold. The held and known pieces all
+// have to be among these, and hold has to be on to use it.
+pub fn unknown_pieces(
+    setup: &[Piece],
+    options: PercentageOptions,
+) -> Result<Vec<PieceType>, String> {
+    let can_hold = options.hold && options.gravity.allows_hold();
+    if options.initial_hold.is_some() && !can_hold {
+        return Err(String::from("the piece in hold can't be used without hold"));
+    }
+    if options.hold_rules.extra_piece().is_some() && !can_hold {
+        return Err(String::from("a piece can't be left in hold without hold"));
+    }
+
+    let mut pieces = setup
+        .iter()
+        .map(|piece| piece.piece_type)
+        .chain(options.hold_rules.extra_piece())
+        .collect::<Vec<PieceType>>();
+    for piece_type in options
+        .initial_hold
+        .iter()
+        .chain(options.known_queue.iter())
+    {
+        match pieces.iter().position(|p| p == piece_type) {
+            Some(i) => pieces.remove(i),
+            None => {
+                return Err(format!(
+                    "the setup doesn't have a {} piece for the hold or queue",
+                    piece_type
+                ))
+            }
+        };
+    }
+    Ok(pieces)
+}
+
+// splits setups into groups made of the same pieces
+pub fn group_setups(setups: Vec<Vec<Piece>>) -> Vec<Vec<Vec<Piece>>> {
+    let mut groups: Vec<(Vec<PieceType>, Vec<Vec<Piece>>)> = Vec::new();
+
+    for setup in setups.into_iter() {
+        let mut key = setup
+            .iter()
+            .map(|piece| piece.piece_type)
+            .collect::<Vec<PieceType>>();
+        key.sort_unstable();
+
+        match groups.iter_mut().find(|(other_key, _)| *other_key == key) {
+            Some((_, group)) => group.push(setup),
+            None => groups.push((key, vec![setup])),
+        }
+    }
+
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+// An order the setup can be built in, with how each piece gets placed and
+// the inputs that place it. Pieces inside the dont_care cells are left out.
+pub fn build_order(
     base_field: &FieldMatrix,
-    piece_perm: &Vec<Piece>,
+    graph: &PlacementGraph,
+    dont_care: &FieldMatrix,
+    options: PercentageOptions,
+) -> Option<Vec<Placement>> {
+    let mut placed = graph
+        .pieces
+        .iter()
+        .map(|piece| piece_in_region(*piece, dont_care))
+        .collect::<Vec<bool>>();
+    let mut order = Vec::new();
+    if extend_build_order(*base_field, graph, &mut placed, &mut order, options) {
+        Some(order)
+    } else {
+        None
+    }
+}
+
+fn extend_build_order(
+    field: FieldMatrix,
+    graph: &PlacementGraph,
+    placed: &mut [bool],
+    order: &mut Vec<Placement>,
     options: PercentageOptions,
 ) -> bool {
-    let mut field = base_field.clone();
-    for piece in piece_perm.iter() {
-        if piece_can_be_placed(*piece, &field, options) {
-            place_piece_on_field(*piece, &mut field);
+    if placed.iter().all(|p| *p) {
+        return true;
+    }
+
+    for (i, piece) in graph.pieces.iter().enumerate() {
+        if placed[i] || graph.predecessors(i).iter().any(|j| !placed[*j]) {
+            continue;
+        }
+        if !piece_can_be_placed(*piece, &field, options) {
+            continue;
+        }
+
+        let mut new_field = field;
+        place_piece_on_field(*piece, &mut new_field);
+        placed[i] = true;
+        order.push(placement(*piece, &field, options));
+        if extend_build_order(new_field, graph, placed, order, options) {
+            return true;
+        }
+        order.pop();
+        placed[i] = false;
+    }
+
+    false
+}
+
+// how the piece gets placed on the field and the inputs that place it
+fn placement(piece: Piece, field: &FieldMatrix, options: PercentageOptions) -> Placement {
+    let spin = if options.spin {
+        placement_spin(
+            piece,
+            field,
+            options.soft_drop,
+            options.rotation_system,
+            options.lock_delay,
+            options.gravity,
+        )
+        .unwrap_or(Spin::NotSpun)
+    } else {
+        Spin::NotSpun
+    };
+    (piece, spin, finesse(piece, field, options))
+}
+
+// The cells extra pieces can be placed in without getting in the setup's
+// way: anywhere beside or above the setup's pieces, but not under any of
+// them, so an extra piece never holds a setup piece up. The dont_care cells
+// are left for the pieces that go there.
+fn extra_region(graph: &PlacementGraph, dont_care: &FieldMatrix) -> FieldMatrix {
+    let mut region = [[1; 10]; 24];
+    for (row, dont_care_row) in region.iter_mut().zip(dont_care.iter()) {
+        for (cell, dont_care_cell) in row.iter_mut().zip(dont_care_row.iter()) {
+            if *dont_care_cell != 0 {
+                *cell = 0;
+            }
+        }
+    }
+
+    for piece in graph.pieces.iter() {
+        if piece_in_region(*piece, dont_care) {
+            continue;
+        }
+        for (x, y) in piece_block_positions(*piece).unwrap().into_iter() {
+            for row in region.iter_mut().skip(y) {
+                row[x] = 0;
+            }
+        }
+    }
+    region
+}
+
+// the field, the spots placed so far and the pieces left
+type BuildState = (FieldMatrix, Vec<bool>, Vec<PieceType>);
+
+// Builds one of the setups from orders of pieces. With extra pieces to
+// place, many queues go through the same states, so those are remembered.
+// When tracing, the pieces placed so far are kept instead.
+struct SetupBuilder<'a> {
+    graph: PlacementGraph,
+    dont_care: &'a FieldMatrix,
+    extras: FieldMatrix,
+    options: PercentageOptions<'a>,
+    memo: Option<HashMap<BuildState, bool>>,
+    trail: Option<Vec<Piece>>,
+}
+
+impl<'a> SetupBuilder<'a> {
+    fn new(
+        graph: PlacementGraph,
+        dont_care: &'a FieldMatrix,
+        options: PercentageOptions<'a>,
+    ) -> SetupBuilder<'a> {
+        let extras = extra_region(&graph, dont_care);
+        let memo = if options.place_extras && options.extra_pieces > 0 {
+            Some(HashMap::new())
         } else {
-            return false;
+            None
+        };
+        SetupBuilder {
+            graph,
+            dont_care,
+            extras,
+            options,
+            memo,
+            trail: None,
+        }
+    }
+
+    // a remembered state doesn't say how it was built, so nothing is
+    // remembered while tracing
+    fn tracing(
+        graph: PlacementGraph,
+        dont_care: &'a FieldMatrix,
+        options: PercentageOptions<'a>,
+    ) -> SetupBuilder<'a> {
+        SetupBuilder {
+            memo: None,
+            trail: Some(Vec::new()),
+            ..SetupBuilder::new(graph, dont_care, options)
+        }
+    }
+
+    // the pieces in the order they get placed, if the order builds the setup
+    fn trace(&mut self, field: &FieldMatrix, order: &[PieceType]) -> Option<Vec<Piece>> {
+        self.trail = Some(Vec::new());
+        if self.builds(field, order) {
+            self.trail.take()
+        } else {
+            None
+        }
+    }
+
+    fn builds(&mut self, field: &FieldMatrix, order: &[PieceType]) -> bool {
+        let mut placed = vec![false; self.graph.pieces.len()];
+        self.place(field, &mut placed, order)
+    }
+
+    fn place(&mut self, field: &FieldMatrix, placed: &mut [bool], order: &[PieceType]) -> bool {
+        if self.memo.is_none() {
+            return self.place_next(field, placed, order);
+        }
+        let key = (*field, placed.to_vec(), order.to_vec());
+        if let Some(works) = self.memo.as_ref().and_then(|memo| memo.get(&key)) {
+            return *works;
+        }
+        let works = self.place_next(field, placed, order);
+        if let Some(memo) = self.memo.as_mut() {
+            memo.insert(key, works);
+        }
+        works
+    }
+
+    // Places the pieces of the setup in order. A piece can go into any spot
+    // of its type that is still empty, so the first L drawn can go into
+    // either L spot. Spots that have to wait for other pieces in the graph
+    // are skipped. When the order has more pieces than there are spots left,
+    // the extra ones can be placed in the extras region instead.
+    fn place_next(
+        &mut self,
+        field: &FieldMatrix,
+        placed: &mut [bool],
+        order: &[PieceType],
+    ) -> bool {
+        let (piece_type, rest) = match order.split_first() {
+            Some(split) => split,
+            None => return true,
+        };
+        let options = self.options;
+
+        let mut tried_dont_care = false;
+        for i in 0..self.graph.pieces.len() {
+            let piece = self.graph.pieces[i];
+            if placed[i] || piece.piece_type != *piece_type {
+                continue;
+            }
+            if self.graph.predecessors(i).iter().any(|j| !placed[*j]) {
+                continue;
+            }
+
+            let placements = if piece_in_region(piece, self.dont_care) {
+                // pieces that can go anywhere in the don't care cells are all
+                // the same, so only try the first one
+                if tried_dont_care {
+                    continue;
+                }
+                tried_dont_care = true;
+                placements_in_region(*piece_type, field, self.dont_care, options).collect()
+            } else if piece_can_be_placed(piece, field, options) {
+                vec![piece]
+            } else {
+                continue;
+            };
+
+            placed[i] = true;
+            for placement in placements.into_iter() {
+                if self.place_piece(field, placement, placed, rest) {
+                    placed[i] = false;
+                    return true;
+                }
+            }
+            placed[i] = false;
+        }
+
+        // An extra piece. Its blocks are never under a setup piece so they
+        // can't hold one up, only get in the way, so it's only worth finding
+        // a spot for if the rest works with it left out. Kicks off the extra
+        // pieces are left out of that.
+        if order.len() > placed.iter().filter(|p| !**p).count()
+            && self.rest_works(field, placed, rest)
+        {
+            // they're just dropped out of the way, there's no need to spin them
+            let drop_options = PercentageOptions {
+                spin: false,
+                ..options
+            };
+            let extras = self.extras;
+            for placement in placements_in_region(*piece_type, field, &extras, drop_options) {
+                if self.place_piece(field, placement, placed, rest) {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    // whether the rest of the order works, without tracing the pieces it
+    // would place
+    fn rest_works(&mut self, field: &FieldMatrix, placed: &mut [bool], rest: &[PieceType]) -> bool {
+        let traced = self.trail.as_ref().map(|trail| trail.len());
+        let works = self.place(field, placed, rest);
+        if let (Some(trail), Some(traced)) = (self.trail.as_mut(), traced) {
+            trail.truncate(traced);
         }
+        works
     }
 
-    true
+    // places the piece and goes on with the rest of the order
+    fn place_piece(
+        &mut self,
+        field: &FieldMatrix,
+        piece: Piece,
+        placed: &mut [bool],
+        rest: &[PieceType],
+    ) -> bool {
+        let mut new_field = *field;
+        place_piece_on_field(piece, &mut new_field);
+        if let Some(trail) = self.trail.as_mut() {
+            trail.push(piece);
+        }
+        if self.place(&new_field, placed, rest) {
+            return true;
+        }
+        if let Some(trail) = self.trail.as_mut() {
+            trail.pop();
+        }
+        false
+    }
 }
 
 pub fn split_color(matrix: FieldMatrix) -> (FieldMatrix, FieldMatrix) {
     // -> (no_color, color)
-    (uncolor(matrix.clone()), get_color(matrix))
+    (uncolor(matrix), get_color(matrix))
 }
 
 fn uncolor(mut matrix: FieldMatrix) -> FieldMatrix {
@@ -86,18 +700,76 @@ fn get_color(mut matrix: FieldMatrix) -> FieldMatrix {
     matrix
 }
 
-pub fn discard_bottom(mut field: FieldMatrix) -> (FieldMatrix, Vec<[u8; 10]>) {
-    let mut old_rows = Vec::new();
+// fumen has a garbage row under the field. With garbage_rows == 0 that row is
+// thrown away and the field moves down to the floor. Otherwise the garbage row
+// is kept, and the bottom garbage_rows rows (counting the garbage row) are
+// treated as pre-existing garbage: colored blocks turn grey, holes stay holes.
+pub fn apply_garbage(mut field: FieldMatrix, garbage_rows: usize) -> FieldMatrix {
+    if garbage_rows == 0 {
+        return discard_bottom(field);
+    }
+
+    for row in field.iter_mut().rev().take(garbage_rows) {
+        for n in row.iter_mut().filter(|n| **n != 0) {
+            *n = 8;
+        }
+    }
 
-    for i in 0..BOTTOM_ROW_DISCARD_COUNT {
-        let u_index = (23 - i) as usize;
+    field
+}
+
+fn discard_bottom(mut field: FieldMatrix) -> FieldMatrix {
+    for y in (1..=FLOOR_ROW).rev() {
+        field[y] = field[y - 1];
+    }
+    field[0] = [0; 10];
+
+    field
+}
+
+// Grey blocks above the bottom garbage_rows rows are a shape to be filled by
+// pieces rather than garbage. -> (garbage, shape)
+pub fn split_grey_region(
+    mut matrix: FieldMatrix,
+    garbage_rows: usize,
+) -> (FieldMatrix, FieldMatrix) {
+    let mut region = [[0; 10]; 24];
+
+    for (y, row) in matrix.iter_mut().enumerate().take(24 - garbage_rows) {
+        for (x, n) in row.iter_mut().enumerate() {
+            if *n == 8 {
+                *n = 0;
+                region[y][x] = 1;
+            }
+        }
+    }
+
+    (matrix, region)
+}
+
+// any filled block in a don't care fumen marks a cell that may be filled by
+// any piece or left empty
+pub fn dont_care_mask(mut matrix: FieldMatrix) -> FieldMatrix {
+    for n in matrix.iter_mut().flatten() {
+        if *n != 0 {
+            *n = 1
+        }
+    }
+    matrix
+}
 
-        old_rows.push(field[u_index].clone());
+// flips the field horizontally, swapping the colors of mirrored pieces (S/Z, L/J)
+pub fn mirror(matrix: FieldMatrix) -> FieldMatrix {
+    let mut mirrored: FieldMatrix = [[0; 10]; 24];
 
-        for block in field[u_index].iter_mut() {
-            *block = 0;
+    for (y, row) in matrix.iter().enumerate() {
+        for (x, n) in row.iter().enumerate() {
+            mirrored[y][9 - x] = match fumen_index_to_piece_type(*n) {
+                Ok(piece_type) => piece_type_to_fumen_index(piece_type.mirror()),
+                Err(_) => *n,
+            };
         }
     }
 
-    (field, old_rows)
+    mirrored
 }
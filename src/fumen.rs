@@ -74,11 +74,11 @@ pub fn decode(fumen_str: &str) -> (FieldMatrix, String) {
                 + (data[i + 4] * 16777216);
             i += 5;
             comment.push(ASC_TABLE.chars().nth(val % 96).unwrap());
-            val = val / 96;
+            val /= 96;
             comment.push(ASC_TABLE.chars().nth(val % 96).unwrap());
-            val = val / 96;
+            val /= 96;
             comment.push(ASC_TABLE.chars().nth(val % 96).unwrap());
-            val = val / 96;
+            val /= 96;
             comment.push(ASC_TABLE.chars().nth(val % 96).unwrap());
         }
         comment = comment[..comment_len].to_string(); // strip padding
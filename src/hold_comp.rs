@@ -1,19 +1,161 @@
-use crate::piece::Piece;
-use permutohedron::Heap;
+// What has to be in hold once the setup is built.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FinalHold<T> {
+    // whatever is left over, a held setup piece gets swapped out for the
+    // next piece
+    Any,
+    Empty,
+    // a piece that isn't part of the setup, drawn from the queue and kept
+    Piece(T),
+}
+
+// Limits on how hold gets used while building the setup.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct HoldRules<T> {
+    pub final_hold: FinalHold<T>,
+    // the most times hold can be pressed
+    pub max_uses: Option<usize>,
+    // whether the first piece of the queue can be held
+    pub first_piece: bool,
+}
 
-struct SmallPerm {
-    pieces: Vec<u8>
+// hold used freely
+impl<T> Default for HoldRules<T> {
+    fn default() -> Self {
+        HoldRules {
+            final_hold: FinalHold::Any,
+            max_uses: None,
+            first_piece: true,
+        }
+    }
 }
 
-impl SmallPerm {
-    pub fn origin(size: usize) -> Self {
-        Self {
-            pieces: vec![0; size]
+impl<T: Copy + PartialEq> HoldRules<T> {
+    // whether hold can be pressed again after being used this many times
+    pub fn can_use(&self, uses: usize) -> bool {
+        self.max_uses.is_none_or(|max| uses < max)
+    }
+
+    // whether the setup can be left with this in hold
+    pub fn ends_with(&self, hold: Option<T>) -> bool {
+        match self.final_hold {
+            FinalHold::Any => true,
+            FinalHold::Empty => hold.is_none(),
+            FinalHold::Piece(piece) => hold == Some(piece),
+        }
+    }
+
+    // the piece drawn on top of the setup's pieces to end up in hold
+    pub fn extra_piece(&self) -> Option<T> {
+        match self.final_hold {
+            FinalHold::Piece(piece) => Some(piece),
+            _ => None,
         }
     }
 }
 
-// works_without_hold contains the indexes of the permutations that work
-pub fn compute_with_hold(pieces: Vec<Piece>, works_without_hold: Vec<usize>) -> u64 {
-    let origin = SmallPerm::origin(pieces.len() - 1);
+// Every order of placement that can be reached from this bag by using hold.
+// The bag works with hold if any of these orders works without hold.
+// See hold_optimization.md
+//
+// held is a piece that's already in hold when the bag starts. Only length
+// pieces get placed, the rest of the bag is left in hold or not drawn at
+// all. With discard, pieces can also be placed out of the way on top of
+// those, so the order can be longer than length. Only the orders that keep
+// to the rules are given.
+pub fn implied_by<T: Copy + Ord>(
+    bag: &[T],
+    held: Option<T>,
+    length: usize,
+    discard: bool,
+    rules: &HoldRules<T>,
+) -> Vec<Vec<T>> {
+    let mut orders = Orders {
+        rules,
+        length,
+        discard,
+        queue_length: bag.len(),
+        orders: Vec::new(),
+    };
+    orders.push(bag, held, 0, 0, &mut Vec::new());
+    let mut orders = orders.orders;
+    orders.sort();
+    orders.dedup();
+    orders
+}
+
+struct Orders<'a, T> {
+    rules: &'a HoldRules<T>,
+    length: usize,
+    discard: bool,
+    queue_length: usize,
+    orders: Vec<Vec<T>>,
+}
+
+impl<'a, T: Copy + PartialEq> Orders<'a, T> {
+    // discarded is how many of the pieces in the order go out of the way
+    fn push(
+        &mut self,
+        bag: &[T],
+        hold: Option<T>,
+        uses: usize,
+        discarded: usize,
+        order: &mut Vec<T>,
+    ) {
+        if order.len() == self.length + discarded {
+            // a piece still to come can be held to end with it in hold
+            let ends = self.rules.ends_with(hold)
+                || (hold.is_none()
+                    && self.rules.can_use(uses)
+                    && bag
+                        .first()
+                        .is_some_and(|next| self.rules.ends_with(Some(*next))));
+            if ends {
+                self.orders.push(order.clone());
+            }
+            return;
+        }
+
+        let (current, rest) = match bag.split_first() {
+            Some((current, rest)) => (*current, rest),
+            None => {
+                // the held piece gets swapped out for the next piece
+                if let Some(held) = hold {
+                    let swaps = self.rules.final_hold == FinalHold::Any && self.rules.can_use(uses);
+                    if swaps && order.len() + 1 == self.length + discarded {
+                        order.push(held);
+                        self.orders.push(order.clone());
+                        order.pop();
+                    }
+                }
+                return;
+            }
+        };
+
+        // place the current piece
+        order.push(current);
+        self.push(rest, hold, uses, discarded, order);
+        if self.discard {
+            self.push(rest, hold, uses, discarded + 1, order);
+        }
+        order.pop();
+
+        let first_piece = bag.len() == self.queue_length;
+        if !self.rules.can_use(uses) || (first_piece && !self.rules.first_piece) {
+            return;
+        }
+
+        // put the current piece in hold, placing whatever was held before
+        match hold {
+            Some(held) => {
+                order.push(held);
+                self.push(rest, Some(current), uses + 1, discarded, order);
+                if self.discard {
+                    self.push(rest, Some(current), uses + 1, discarded + 1, order);
+                }
+                order.pop();
+            }
+            None => self.push(rest, Some(current), uses + 1, discarded, order),
+        }
+    }
 }
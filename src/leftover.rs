@@ -0,0 +1,132 @@
+use crate::bag::Bag;
+use crate::field::{find_working_queues, PercentageOptions};
+use crate::fieldmatrix::FieldMatrix;
+use crate::hold_comp::{FinalHold, HoldRules};
+use crate::piece::{Piece, PieceType, PIECE_TYPES};
+use crate::randomizer::Randomizer;
+use std::collections::{HashMap, HashSet};
+
+// A way of building a group of setups: the queues it works for, all of the
+// same length, and the piece it leaves in hold.
+struct Finish {
+    queues: HashSet<Vec<PieceType>>,
+    length: usize,
+    held: Option<PieceType>,
+}
+
+// The pieces left over once a setup is built, over every queue the bag can
+// deal that builds one. That's the piece left in hold and whatever the bag
+// being dealt still has, so once a setup draws into the next bag it's the
+// rest of that bag. With hold, a piece that isn't part of the setup can be
+// kept in hold to build around it. Extra pieces aren't dealt here, so
+// options.extra_pieces has to be 0.
+//
+// Every group of setups is tried on each queue, so the groups can leave
+// different pieces over. Each set of leftover pieces comes with the
+// percentage of the queues building a setup that leave it, most likely
+// first. The player picks one when a queue can leave several, so its chance
+// is split evenly between them and the sets add up to 100%.
+pub fn leftover_report(
+    base_field: FieldMatrix,
+    groups: &[Vec<Vec<Piece>>],
+    dont_care: &FieldMatrix,
+    options: PercentageOptions,
+    bag: &Bag,
+) -> Vec<(Vec<PieceType>, f64)> {
+    let mut hold_rules = vec![options.hold_rules];
+    let can_hold = options.hold && options.gravity.allows_hold();
+    if can_hold && options.hold_rules.final_hold == FinalHold::Any {
+        hold_rules.extend(PIECE_TYPES.iter().map(|piece_type| HoldRules {
+            final_hold: FinalHold::Piece(*piece_type),
+            ..options.hold_rules
+        }));
+    }
+
+    let mut finishes = Vec::new();
+    for group in groups.iter() {
+        for rules in hold_rules.iter() {
+            let options = PercentageOptions {
+                hold_rules: *rules,
+                ..options
+            };
+            let queues = find_working_queues(base_field, group.clone(), dont_care, options);
+            let length = match queues.first() {
+                Some((queue, _)) => queue.len(),
+                None => continue,
+            };
+            finishes.push(Finish {
+                queues: queues
+                    .into_iter()
+                    .filter(|(_, works)| *works)
+                    .map(|(queue, _)| queue)
+                    .collect(),
+                length,
+                held: rules.extra_piece(),
+            });
+        }
+    }
+
+    let length = finishes.iter().map(|finish| finish.length).max();
+    let length = match length {
+        Some(length) => length,
+        None => return Vec::new(),
+    };
+
+    let mut total = 0.0;
+    let mut leftovers: HashMap<Vec<PieceType>, f64> = HashMap::new();
+    for queue in bag.deals(length).into_iter() {
+        if !queue.starts_with(options.known_queue) {
+            continue;
+        }
+
+        let mut found = HashSet::new();
+        for finish in finishes.iter() {
+            let dealt = &queue[..finish.length];
+            if finish.queues.contains(dealt) {
+                let mut leftover = bag.after(dealt);
+                leftover.extend(finish.held);
+                leftover.sort_unstable();
+                found.insert(leftover);
+            }
+        }
+        if found.is_empty() {
+            continue;
+        }
+
+        let chance = bag.queue_chance(&queue);
+        total += chance;
+        let share = chance / found.len() as f64;
+        for leftover in found.into_iter() {
+            *leftovers.entry(leftover).or_insert(0.0) += share;
+        }
+    }
+    if total == 0.0 {
+        return Vec::new();
+    }
+
+    let mut report = leftovers
+        .into_iter()
+        .map(|(leftover, chance)| (leftover, chance / total * 100.0))
+        .collect::<Vec<(Vec<PieceType>, f64)>>();
+    report.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+    report
+}
+
+// How often each piece is left over, out of the same queues as the report
+// it's worked out from. Pieces that are never left over aren't listed.
+pub fn leftover_pieces(report: &[(Vec<PieceType>, f64)]) -> Vec<(PieceType, f64)> {
+    let mut pieces = PIECE_TYPES
+        .iter()
+        .map(|piece_type| {
+            let percent = report
+                .iter()
+                .filter(|(leftover, _)| leftover.contains(piece_type))
+                .map(|(_, percent)| percent)
+                .sum::<f64>();
+            (*piece_type, percent)
+        })
+        .filter(|(_, percent)| *percent > 0.0)
+        .collect::<Vec<(PieceType, f64)>>();
+    pieces.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    pieces
+}
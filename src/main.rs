@@ -1,4 +1,10 @@
 use clap::{App, Arg};
+use hold_comp::FinalHold;
+use piece::dependency::PlacementGraph;
+use piece::gravity::Gravity;
+use piece::lock_delay::LockDelay;
+use piece::rotation_system::RotationSystem;
+use std::thread;
 
 mod field;
 mod fieldmatrix;
@@ -7,6 +13,11 @@ mod perm_gen;
 mod piece;
 mod bag;
 mod hold_comp;
+mod tiling;
+mod preview;
+mod randomizer;
+mod leftover;
+mod monte_carlo;
 
 #[cfg(test)]
 mod tests;
@@ -18,7 +29,6 @@ add --pattern method for custom piece drop orders
 figure out how to do the hold optimization
 verbose information
 bugfixing
-two pieces of same type can go in either spot
 
 caching softdrop paths? maybe if needed
 
@@ -54,33 +64,874 @@ fn main() {
                 .long("norotation")
                 .help("put to not use rotation/spins when calculating"),
         )
+        .arg(
+            Arg::with_name("garbage")
+                .short("g")
+                .long("garbage")
+                .help(
+                    "number of rows at the bottom of the fumen, counting the garbage row, \
+                     to keep as pre-existing garbage. 0 throws away the garbage row",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("dont_care")
+                .short("d")
+                .long("dontcare")
+                .help(
+                    "a second fumen marking cells that can be filled by any piece or left \
+                     empty. Pieces drawn completely inside them can go anywhere inside them",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tile")
+                .long("tile")
+                .help("put to fill the grey blocks above the garbage rows with pieces"),
+        )
+        .arg(
+            Arg::with_name("tile_pieces")
+                .long("tilepieces")
+                .help("the pieces the grey shape has to be filled with, e.g. LJSZ")
+                .requires("tile")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mirror")
+                .short("m")
+                .long("mirror")
+                .help("put to calculate the mirrored setup (S/Z and L/J swapped)"),
+        )
+        .arg(
+            Arg::with_name("rotation_system")
+                .long("rotationsystem")
+                .help("the rotation system pieces turn with: srs, srs+ (with 180s), ars or nrs")
+                .takes_value(true)
+                .default_value("srs"),
+        )
+        .arg(
+            Arg::with_name("ruleset")
+                .long("ruleset")
+                .help(
+                    "a TOML or JSON file with the kicks, spawns and where the SRS pieces sit \
+                     in their boxes, to use instead of a built in rotation system. The piece \
+                     shapes can't be changed",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("move_resets")
+                .long("movereset")
+                .help(
+                    "how many moves and rotations a piece gets on the ground before it \
+                     locks, e.g. 15 for guideline games. Unlimited if left out",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no_step_reset")
+                .long("nostepreset")
+                .help("put to not give the moves back when a piece falls to a new lowest row")
+                .requires("move_resets"),
+        )
+        .arg(Arg::with_name("twenty_g").long("20g").help(
+            "put to drop pieces onto the stack the moment they spawn and after every \
+                     move, like at 20G in TGM. Pieces are always turned on the stack",
+        ))
+        .arg(
+            Arg::with_name("irs")
+                .long("irs")
+                .help("put to let pieces be turned before they appear at 20G")
+                .requires("twenty_g"),
+        )
+        .arg(
+            Arg::with_name("ihs")
+                .long("ihs")
+                .help("put to let pieces be held before they appear, the only hold at 20G")
+                .requires("twenty_g"),
+        )
+        .arg(
+            Arg::with_name("max_inputs")
+                .long("maxinputs")
+                .help(
+                    "also give the percentage when every piece has to be placed within this \
+                     many inputs, not counting the hard drop",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("previews")
+                .long("previews")
+                .help(
+                    "also give the percentage when only this many pieces after the current \
+                     one can be seen when placing it",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("randomizer")
+                .long("randomizer")
+                .help(
+                    "weight the queues by how often a game's randomizer deals them: 7bag, \
+                     14bag, random, tgm1, tgm3 or nes",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("used")
+                .long("used")
+                .help(
+                    "the pieces already dealt from the current bag when the setup starts, \
+                     e.g. TIO. Only the queues the bag can still deal are counted",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("next_bag")
+                .long("nextbag")
+                .help(
+                    "the most pieces the setup may draw from the bag after the current one. \
+                     Extra pieces past that are never drawn",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("initial_hold")
+                .long("hold")
+                .help("the piece already in hold when the setup starts")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("known_queue")
+                .long("queue")
+                .help("the first pieces of the queue if they're already known, current piece first")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("end_hold")
+                .long("endhold")
+                .help(
+                    "what has to be in hold once the setup is built: a piece that isn't part \
+                     of the setup, like T, or empty",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .long("threads")
+                .help("how many threads to check the queues on, every core by default")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("samples")
+                .long("samples")
+                .help(
+                    "estimate the percentage from this many random queues instead of going \
+                     through all of them",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("precision")
+                .long("precision")
+                .help(
+                    "stop sampling once the 95% confidence interval is within this many \
+                     percent of the estimate",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .help("the seed for the random queues, the same seed gives the same estimate")
+                .default_value("0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("extra_pieces")
+                .long("extra")
+                .help(
+                    "how many more pieces the queue has than the setup needs. They can be \
+                     left in hold or not drawn at all",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("place_extras")
+                .long("placeextras")
+                .help("put to let the extra pieces be placed beside or above the setup's pieces"),
+        )
+        .arg(
+            Arg::with_name("max_holds")
+                .long("maxholds")
+                .help("the most times hold can be used while building the setup")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no_first_hold")
+                .long("nofirsthold")
+                .help("put to not hold the first piece of the queue"),
+        )
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .help(
+                    "put to print an order each setup can be built in, with the spins and \
+                     inputs used. Given a queue, prints how that queue builds a setup instead",
+                )
+                .takes_value(true)
+                .min_values(0),
+        )
+        .arg(
+            Arg::with_name("no_das")
+                .long("nodas")
+                .help("put to only tap pieces over in the traced and exported inputs"),
+        )
+        .arg(
+            Arg::with_name("export")
+                .long("export")
+                .help(
+                    "file to write an order each setup can be built in to as CSV, with the \
+                     fewest inputs that place each piece",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("leftover")
+                .long("leftover")
+                .help(
+                    "put to also give how often each set of pieces, and each piece, is left \
+                     over in hold and the bag once a setup is built",
+                ),
+        )
+        .arg(
+            Arg::with_name("prefix")
+                .long("prefix")
+                .help(
+                    "also give a table of the percentage for every way the queue can start \
+                     with this many pieces",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tspin")
+                .long("tspin")
+                .help("put to also give the percentage of the setup leaving a T-spin open"),
+        )
+        .arg(
+            Arg::with_name("dot")
+                .long("dot")
+                .help(
+                    "file to write the order the setup's pieces have to be placed in to, \
+                     as a Graphviz DOT graph",
+                )
+                .takes_value(true),
+        )
         .get_matches();
 
-    let (fumen_field, _comment) = fumen::decode(matches.value_of("tetfu").unwrap());
-    let (fumen_field, _) = field::discard_bottom(fumen_field);
-    // todo set up to use softdrop
+    let garbage_rows = match matches.value_of("garbage").unwrap().parse::<usize>() {
+        Ok(rows) if rows <= 24 => rows,
+        _ => {
+            println!("error: garbage must be a number of rows from 0 to 24");
+            return;
+        }
+    };
+    let prepare_field = |fumen_str: &str| {
+        let (fumen_field, _comment) = fumen::decode(fumen_str);
+        field::apply_garbage(fumen_field, garbage_rows)
+    };
+    let mirror_field = |fumen_field| {
+        if matches.is_present("mirror") {
+            field::mirror(fumen_field)
+        } else {
+            fumen_field
+        }
+    };
+
+    // the setup's pieces are read off the field as it is and mirrored with
+    // the rest of it once they're found
+    let fumen_field = prepare_field(matches.value_of("tetfu").unwrap());
+    let (_, color_field) = field::split_color(fumen_field);
+    let fumen_field = mirror_field(fumen_field);
+    let dont_care = match matches.value_of("dont_care") {
+        Some(mask) => field::dont_care_mask(mirror_field(prepare_field(mask))),
+        None => [[0; 10]; 24],
+    };
 
-    let (base_field, color_field) = field::split_color(fumen_field);
+    let (base_field, _) = field::split_color(fumen_field);
+    let (base_field, grey_region) = if matches.is_present("tile") {
+        field::split_grey_region(base_field, garbage_rows)
+    } else {
+        (base_field, [[0; 10]; 24])
+    };
 
-    let options = field::PercentageOptions::new(
+    let mut options = field::PercentageOptions::new(
         !matches.is_present("no_hold"),
-        !matches.is_present("no_rotation"),
         !matches.is_present("no_softdrop"),
+        !matches.is_present("no_rotation"),
     );
+    options.das = !matches.is_present("no_das");
+    let max_inputs = match matches.value_of("max_inputs").map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) => Some(n),
+        Some(Err(_)) => {
+            println!("error: maxinputs must be a number of inputs");
+            return;
+        }
+        None => None,
+    };
+    let previews = match matches.value_of("previews").map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) => Some(n),
+        Some(Err(_)) => {
+            println!("error: previews must be a number of pieces");
+            return;
+        }
+        None => None,
+    };
+    if matches.is_present("twenty_g") {
+        options.gravity = Gravity::TwentyG {
+            irs: matches.is_present("irs"),
+            ihs: matches.is_present("ihs"),
+        };
+    }
+    if let Some(limit) = matches.value_of("move_resets") {
+        let limit = match limit.parse::<usize>() {
+            Ok(limit) => limit,
+            Err(_) => {
+                println!("error: movereset must be a number of moves");
+                return;
+            }
+        };
+        options.lock_delay = LockDelay::MoveReset {
+            limit,
+            step_reset: !matches.is_present("no_step_reset"),
+        };
+    }
+    let ruleset = match matches.value_of("ruleset").map(piece::ruleset::Ruleset::load) {
+        Some(Ok(ruleset)) => Some(ruleset),
+        Some(Err(e)) => {
+            println!("error: {}", e);
+            return;
+        }
+        None => None,
+    };
+    options.rotation_system = match &ruleset {
+        Some(ruleset) => RotationSystem::Custom(ruleset),
+        None => match RotationSystem::from_name(matches.value_of("rotation_system").unwrap()) {
+            Ok(system) => system,
+            Err(e) => {
+                println!("error: {}", e);
+                return;
+            }
+        },
+    };
+    let randomizer = match matches.value_of("randomizer").map(randomizer::from_name) {
+        Some(Ok(randomizer)) => Some(randomizer),
+        Some(Err(e)) => {
+            println!("error: {}", e);
+            return;
+        }
+        None => None,
+    };
+    options.randomizer = randomizer.as_deref();
+
+    let parse_pieces = |queue: &str| {
+        let queue = if matches.is_present("mirror") {
+            piece::mirror_queue(queue)?
+        } else {
+            queue.to_string()
+        };
+        piece::parse_queue(&queue)
+    };
+    let initial_hold = matches.value_of("initial_hold").map(parse_pieces);
+    options.initial_hold = match initial_hold {
+        Some(Ok(held)) if held.len() == 1 => Some(held[0]),
+        Some(Ok(_)) => {
+            println!("error: only one piece fits in hold");
+            return;
+        }
+        Some(Err(e)) => {
+            println!("error: {}", e);
+            return;
+        }
+        None => None,
+    };
+    let known_queue = match matches.value_of("known_queue").map(parse_pieces) {
+        Some(Ok(queue)) => queue,
+        Some(Err(e)) => {
+            println!("error: {}", e);
+            return;
+        }
+        None => Vec::new(),
+    };
+    options.known_queue = &known_queue;
+    options.hold_rules.final_hold = match matches.value_of("end_hold") {
+        Some(end_hold) if end_hold.to_lowercase() == "empty" => FinalHold::Empty,
+        Some(end_hold) => match parse_pieces(end_hold) {
+            Ok(piece) if piece.len() == 1 => FinalHold::Piece(piece[0]),
+            Ok(_) => {
+                println!("error: only one piece fits in hold");
+                return;
+            }
+            Err(e) => {
+                println!("error: {}", e);
+                return;
+            }
+        },
+        None => FinalHold::Any,
+    };
+    options.hold_rules.max_uses = match matches.value_of("max_holds").map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) => Some(n),
+        Some(Err(_)) => {
+            println!("error: maxholds must be a number of holds");
+            return;
+        }
+        None => None,
+    };
+    options.hold_rules.first_piece = !matches.is_present("no_first_hold");
+    options.extra_pieces = match matches.value_of("extra_pieces").map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            println!("error: extra must be a number of pieces");
+            return;
+        }
+        None => 0,
+    };
+    options.place_extras = matches.is_present("place_extras");
+    if previews.is_some() && options.extra_pieces > 0 {
+        println!("error: previews don't work with extra pieces");
+        return;
+    }
+    if matches.is_present("leftover") && (options.extra_pieces > 0 || options.place_extras) {
+        println!("error: leftover doesn't work with extra pieces");
+        return;
+    }
+    options.threads = match matches.value_of("threads").map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n > 0 => n,
+        Some(_) => {
+            println!("error: threads must be a number of threads above 0");
+            return;
+        }
+        None => thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+    let samples = match matches.value_of("samples").map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) => Some(n),
+        Some(Err(_)) => {
+            println!("error: samples must be a number of queues");
+            return;
+        }
+        None => None,
+    };
+    let precision = match matches.value_of("precision").map(|n| n.parse::<f64>()) {
+        Some(Ok(p)) if p > 0.0 => Some(p),
+        Some(_) => {
+            println!("error: precision must be a percentage above 0");
+            return;
+        }
+        None => None,
+    };
+    let seed = match matches.value_of("seed").unwrap().parse::<u64>() {
+        Ok(seed) => seed,
+        Err(_) => {
+            println!("error: seed must be a whole number");
+            return;
+        }
+    };
+    let prefix = match matches.value_of("prefix").map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) => Some(n),
+        Some(Err(_)) => {
+            println!("error: prefix must be a number of pieces");
+            return;
+        }
+        None => None,
+    };
+    let enumeration_only = ["tspin", "previews", "max_inputs", "leftover", "prefix"];
+    if samples.is_some() && enumeration_only.iter().any(|arg| matches.is_present(arg)) {
+        println!(
+            "error: samples can't be used with tspin, previews, maxinputs, leftover or prefix"
+        );
+        return;
+    }
+
+    // without a randomizer every order of a bag's pieces counts the same,
+    // like a 7-bag deals them
+    let num_rep = match options.randomizer {
+        Some(randomizer) => randomizer.bag_repeats(),
+        None => Some(1),
+    };
+
+    // a setup starting partway through a bag
+    let mut bag = None;
+    if matches.is_present("used") || matches.is_present("next_bag") {
+        let num_rep = match num_rep {
+            Some(num_rep) => num_rep,
+            None => {
+                println!("error: used and nextbag only work with the 7bag or 14bag randomizer");
+                return;
+            }
+        };
+        let used = parse_pieces(matches.value_of("used").unwrap_or(""))
+            .and_then(|used| bag::Bag::with_used(num_rep, &used));
+        let next_bag = match matches.value_of("next_bag").map(|n| n.parse::<usize>()) {
+            Some(Ok(n)) => n,
+            Some(Err(_)) => {
+                println!("error: nextbag must be a number of pieces");
+                return;
+            }
+            None => 7 * num_rep as usize,
+        };
+        match used {
+            Ok(used) => bag = Some((used, next_bag)),
+            Err(e) => {
+                println!("error: {}", e);
+                return;
+            }
+        }
+    }
+    if let Some((used, _)) = &bag {
+        options.randomizer = Some(used);
+    }
+    if previews.is_some() && options.randomizer.is_some() {
+        println!("error: previews don't work with a randomizer or used pieces");
+        return;
+    }
+
+    let mut setups = match piece::color_field_to_setups(color_field) {
+        Ok(setups) => setups,
+        Err(e) => {
+            println!("error: {}", e);
+            return;
+        }
+    };
+    if matches.is_present("mirror") {
+        setups = setups
+            .iter()
+            .map(|pieces| pieces.iter().map(|piece| piece.mirror()).collect())
+            .collect();
+    }
+
+    if matches.is_present("tile") {
+        let tile_pieces = match matches.value_of("tile_pieces") {
+            Some(queue) if matches.is_present("mirror") => piece::mirror_queue(queue),
+            Some(queue) => Ok(queue.to_string()),
+            None => Ok(String::new()),
+        }
+        .and_then(|queue| piece::parse_queue(&queue));
+        let tile_pieces = match tile_pieces {
+            Ok(tile_pieces) => tile_pieces,
+            Err(e) => {
+                println!("error: {}", e);
+                return;
+            }
+        };
+        let piece_counts = if matches.is_present("tile_pieces") {
+            Some(&tile_pieces[..])
+        } else {
+            None
+        };
+
+        let candidates = tiling::region_candidates(&grey_region);
+        let tilings = tiling::tile_region(&grey_region, &candidates, piece_counts);
+        if tilings.is_empty() {
+            println!("error: no way to fill the grey blocks with pieces");
+            return;
+        }
+
+        setups = setups
+            .iter()
+            .flat_map(|setup| {
+                tilings
+                    .iter()
+                    .map(move |tiling| setup.iter().chain(tiling.iter()).copied().collect())
+            })
+            .collect();
+    }
+
+    // pieces inside the don't care cells get placed somewhere else anyway
+    let required_pieces = |pieces: &Vec<piece::Piece>| {
+        pieces
+            .iter()
+            .filter(|piece| !piece::piece_in_region(**piece, &dont_care))
+            .copied()
+            .collect::<Vec<piece::Piece>>()
+    };
+    let first_impossibilities = piece::impossibilites(&required_pieces(&setups[0]), &fumen_field);
+    setups
+        .retain(|pieces| piece::impossibilites(&required_pieces(pieces), &fumen_field).is_empty());
+    if setups.is_empty() {
+        println!(
+            "error: {}",
+            piece::format_pieces(&first_impossibilities, "impossible to place pieces:\n")
+        );
+        return;
+    }
+
+    let unknown = match field::unknown_pieces(&setups[0], options) {
+        Ok(unknown) => unknown,
+        Err(e) => {
+            println!("error: {}", e);
+            return;
+        }
+    };
+    if let Some((bag, next_bag)) = &bag {
+        // the known pieces and the piece left in hold get drawn too
+        let length = options.known_queue.len() + unknown.len();
+        options.extra_pieces = match bag.extras_within(length, options.extra_pieces, *next_bag) {
+            Ok(extra_pieces) => extra_pieces,
+            Err(e) => {
+                println!("error: {}", e);
+                return;
+            }
+        };
+    }
+
+    // setups no order of placing the pieces can build
+    let graph =
+        |pieces: &Vec<piece::Piece>| PlacementGraph::new(pieces, &base_field, &dont_care, options);
+    let first_graph = graph(&setups[0]);
+    setups.retain(|pieces| {
+        let graph = graph(pieces);
+        graph.find_order().is_some() && graph.held_up_by_later_pieces().is_empty()
+    });
+    if setups.is_empty() {
+        let held_up = first_graph
+            .held_up_by_later_pieces()
+            .iter()
+            .map(|i| first_graph.pieces[*i])
+            .collect::<Vec<piece::Piece>>();
+        if held_up.is_empty() {
+            println!("error: no order of placing the pieces builds the setup");
+        } else {
+            println!(
+                "error: {}",
+                piece::format_pieces(
+                    &held_up,
+                    "pieces only held up by pieces placed after them:\n"
+                )
+            );
+        }
+        return;
+    }
+
+    if let Some(path) = matches.value_of("dot") {
+        let dot = setups
+            .iter()
+            .enumerate()
+            .map(|(i, pieces)| graph(pieces).to_dot(&format!("setup{}", i + 1)))
+            .collect::<Vec<String>>()
+            .join("\n");
+        if let Err(e) = std::fs::write(path, dot) {
+            println!("error: could not write {}: {}", path, e);
+            return;
+        }
+    }
+
+    if matches.is_present("trace") || matches.is_present("export") {
+        let orders = setups
+            .iter()
+            .map(|pieces| field::build_order(&base_field, &graph(pieces), &dont_care, options))
+            .collect::<Vec<_>>();
+
+        if let Some(queue) = matches.value_of("trace") {
+            let traced = parse_pieces(queue).map(|pieces| {
+                field::trace_queue(base_field, &setups, &dont_care, options, &pieces)
+            });
+            match traced {
+                Ok(Some((i, order))) => {
+                    let title = format!("{} builds setup {}:\n", queue, i + 1);
+                    print!("{}", piece::format_placements(&order, &title));
+                }
+                Ok(None) => println!("{} doesn't build any setup", queue),
+                Err(e) => {
+                    println!("error: {}", e);
+                    return;
+                }
+            }
+        } else if matches.is_present("trace") {
+            for (i, order) in orders.iter().enumerate() {
+                let title = format!("setup {}:\n", i + 1);
+                match order {
+                    Some(order) => print!("{}", piece::format_placements(order, &title)),
+                    None => println!("{}can't be built", title),
+                }
+            }
+        }
+
+        if let Some(path) = matches.value_of("export") {
+            let mut csv = String::from("setup,piece,x,y,spin,inputs\n");
+            for (i, order) in orders.iter().enumerate() {
+                for (piece, spin, inputs) in order.iter().flatten() {
+                    let inputs = inputs
+                        .as_ref()
+                        .map(|inputs| piece::finesse::format_inputs(inputs))
+                        .unwrap_or_default();
+                    csv.push_str(&format!(
+                        "{},{},{},{},{},\"{}\"\n",
+                        i + 1,
+                        piece.piece_type,
+                        piece.position.0,
+                        piece.position.1,
+                        spin,
+                        inputs
+                    ));
+                }
+            }
+            if let Err(e) = std::fs::write(path, csv) {
+                println!("error: could not write {}: {}", path, e);
+                return;
+            }
+        }
+    }
 
-    match piece::color_field_to_pieces(color_field) {
-        Ok(pieces) => {
-            let impossibilities = piece::impossibilites(&pieces, &fumen_field);
-            if !impossibilities.is_empty() {
-                println!(
-                    "error: {}",
-                    piece::format_pieces(&impossibilities, "impossible to place pieces:\n")
-                );
+    // the field each setup leaves behind, checked for a T-spin slot
+    let tspin_open = |pieces: &Vec<piece::Piece>| {
+        let mut field = base_field;
+        for piece in pieces.iter() {
+            piece::place_piece_on_field(*piece, &mut field);
+        }
+        piece::movement::tspin_available(
+            &field,
+            options.rotation_system,
+            options.lock_delay,
+            options.gravity,
+        )
+    };
+
+    let groups = field::group_setups(setups);
+    let group_count = groups.len();
+    let leftovers = if matches.is_present("leftover") {
+        // the whole bag is dealt when the setup doesn't start partway through one
+        let whole_bag = num_rep.map(bag::Bag::new);
+        match bag.as_ref().map(|(bag, _)| bag).or(whole_bag.as_ref()) {
+            Some(bag) => Some(leftover::leftover_report(
+                base_field, &groups, &dont_care, options, bag,
+            )),
+            None => {
+                println!("error: leftover only works with the 7bag or 14bag randomizer");
                 return;
             }
-            let percent = field::find_percentage(base_field, pieces, options);
-            println!("{}%", percent);
         }
-        Err(e) => println!("error: {}", e),
+    } else {
+        None
     };
+    for group in groups.into_iter() {
+        let pieces = group[0]
+            .iter()
+            .map(|piece| piece.piece_type.to_string())
+            .collect::<String>();
+
+        if let Some(samples) = samples {
+            let estimate = monte_carlo::estimate_percentage(
+                base_field, group, &dont_care, options, samples, precision, seed,
+            );
+            let mut percent = format!(
+                "about {}% (95% confidence: {}% to {}%, from {} queues)",
+                estimate.percent, estimate.low, estimate.high, estimate.drawn
+            );
+            if options.randomizer.is_some() {
+                percent.push_str(&format!(
+                    " (worth {:.0} queues weighted by how likely they are)",
+                    estimate.effective
+                ));
+            }
+            if group_count == 1 {
+                println!("{}", percent);
+            } else {
+                println!("{}: {}", pieces, percent);
+            }
+            continue;
+        }
+        let queues = field::find_working_queues(base_field, group.clone(), &dont_care, options);
+        let mut percent = format!("{}%", field::percentage(&queues, options));
+        if let Some(randomizer) = options.randomizer {
+            percent.push_str(&format!(
+                " (the pieces come up {}% of the time)",
+                field::deal_chance(&queues, randomizer)
+            ));
+        }
+        if matches.is_present("tspin") {
+            let tspin_group = group
+                .iter()
+                .filter(|pieces| tspin_open(pieces))
+                .cloned()
+                .collect::<Vec<Vec<piece::Piece>>>();
+            let tspin_percent = if tspin_group.is_empty() {
+                0.0
+            } else {
+                field::find_percentage(base_field, tspin_group, &dont_care, options)
+            };
+            percent.push_str(&format!(", with the T-spin open: {}%", tspin_percent));
+        }
+
+        if let Some(previews) = previews {
+            let preview_percent = preview::find_preview_percentage(
+                base_field,
+                group.clone(),
+                &dont_care,
+                options,
+                previews,
+            );
+            percent.push_str(&format!(
+                ", with {} previews: {}%",
+                previews, preview_percent
+            ));
+        }
+
+        // queues that only work with more inputs than the budget
+        let mut slow_queues = Vec::new();
+        if let Some(max_inputs) = max_inputs {
+            let budget = field::PercentageOptions {
+                max_inputs: Some(max_inputs),
+                ..options
+            };
+            let fast_queues = field::find_working_queues(base_field, group, &dont_care, budget);
+            let slow = queues
+                .iter()
+                .zip(fast_queues.iter())
+                .map(|((queue, works), (_, works_fast))| (queue.clone(), *works && !*works_fast))
+                .collect::<Vec<(Vec<piece::PieceType>, bool)>>();
+            slow_queues = slow
+                .iter()
+                .filter(|(_, slow)| *slow)
+                .map(|(queue, _)| queue.iter().map(|p| p.to_string()).collect::<String>())
+                .collect::<Vec<String>>();
+            percent.push_str(&format!(
+                ", within {} inputs: {}% ({}% technically possible but slow)",
+                max_inputs,
+                field::percentage(&fast_queues, options),
+                field::percentage(&slow, options)
+            ));
+        }
+
+        if group_count == 1 {
+            println!("{}", percent);
+        } else {
+            println!("{}: {}", pieces, percent);
+        }
+        if matches.is_present("trace") && !slow_queues.is_empty() {
+            println!("technically possible but slow:\n{}", slow_queues.join("\n"));
+        }
+        // tab separated so it can be sorted by any column
+        if let Some(prefix) = prefix {
+            println!("prefix\tpercentage\tchance of prefix");
+            for (start, percent, share) in field::prefix_percentages(&queues, prefix, options) {
+                let start = start.iter().map(|p| p.to_string()).collect::<String>();
+                println!("{}\t{}%\t{}%", start, percent, share);
+            }
+        }
+    }
+    if let Some(leftovers) = leftovers {
+        println!("left over, out of the queues that build a setup:");
+        for (leftover, percent) in leftovers.iter() {
+            let pieces = if leftover.is_empty() {
+                String::from("nothing")
+            } else {
+                leftover.iter().map(|p| p.to_string()).collect::<String>()
+            };
+            println!("{}: {}%", pieces, percent);
+        }
+        for (piece_type, percent) in leftover::leftover_pieces(&leftovers).iter() {
+            println!("{} left over: {}%", piece_type, percent);
+        }
+    }
 }
@@ -0,0 +1,137 @@
+use crate::field::{queue_checker, unknown_pieces, PercentageOptions};
+use crate::fieldmatrix::FieldMatrix;
+use crate::piece::{Piece, PieceType, PIECE_TYPES};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+// how many queues have to be drawn before the interval is narrow enough
+// to stop on
+const MIN_SAMPLES: usize = 100;
+// for a 95% confidence interval
+const Z: f64 = 1.96;
+
+pub struct Estimate {
+    pub percent: f64,
+    // the 95% confidence interval, as percentages
+    pub low: f64,
+    pub high: f64,
+    // how many queues were drawn, and how many unweighted queues they're
+    // worth once weighted by how likely they are
+    pub drawn: usize,
+    pub effective: f64,
+}
+
+impl Estimate {
+    // The Wilson score interval, which keeps to 0-100% even when almost
+    // every queue works or almost none do. Weighted queues count as the
+    // effective number of unweighted ones (Kish's).
+    fn new(works: f64, total: f64, squares: f64, drawn: usize) -> Estimate {
+        if total == 0.0 {
+            return Estimate {
+                percent: 0.0,
+                low: 0.0,
+                high: 100.0,
+                drawn,
+                effective: 0.0,
+            };
+        }
+
+        let n = total * total / squares;
+        let p = works / total;
+        let z2 = Z * Z;
+        let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+        let half = Z * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / (1.0 + z2 / n);
+        Estimate {
+            percent: p * 100.0,
+            low: (center - half).max(0.0) * 100.0,
+            high: (center + half).min(1.0) * 100.0,
+            drawn,
+            effective: n,
+        }
+    }
+
+    // half the width of the interval
+    pub fn margin(&self) -> f64 {
+        (self.high - self.low) / 2.0
+    }
+}
+
+// Estimates find_percentage from random queues instead of going through
+// every one of them, for setups with too many pieces to go through. Up to
+// samples queues are drawn, stopping early once the interval is within
+// precision of the estimate. The same seed always draws the same queues.
+//
+// The queues are the known pieces, then the setup's pieces shuffled with
+// any extra pieces. Each one counts as often as find_percentage counts it:
+// weighted by how likely the randomizer is to deal it, every distinct queue
+// the same without one. So every queue drawn counts, even when the
+// randomizer seldom deals the setup's pieces.
+pub fn estimate_percentage(
+    base_field: FieldMatrix,
+    setups: Vec<Vec<Piece>>,
+    dont_care: &FieldMatrix,
+    options: PercentageOptions,
+    samples: usize,
+    precision: Option<f64>,
+    seed: u64,
+) -> Estimate {
+    let unknown = match unknown_pieces(&setups[0], options) {
+        Ok(unknown) => unknown,
+        Err(_) => return Estimate::new(0.0, 0.0, 0.0, 0),
+    };
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut queue_works = queue_checker(base_field, &setups, dont_care, options);
+    let (mut works, mut total, mut squares) = (0.0, 0.0, 0.0);
+    let mut estimate = Estimate::new(0.0, 0.0, 0.0, 0);
+    for drawn in 1..=samples {
+        let extras = (0..options.extra_pieces)
+            .map(|_| *PIECE_TYPES.choose(&mut rng).unwrap())
+            .collect::<Vec<PieceType>>();
+        let mut rest = unknown.clone();
+        rest.extend(extras.iter());
+        rest.shuffle(&mut rng);
+        let mut queue = options.known_queue.to_vec();
+        queue.extend(rest);
+
+        let weight = keep_chance(&unknown, &extras)
+            * match options.randomizer {
+                Some(randomizer) => randomizer.queue_chance(&queue),
+                None => 1.0,
+            };
+        if weight > 0.0 {
+            total += weight;
+            squares += weight * weight;
+            if queue_works(&queue) {
+                works += weight;
+            }
+        }
+
+        estimate = Estimate::new(works, total, squares, drawn);
+        if let Some(precision) = precision {
+            if drawn >= MIN_SAMPLES && estimate.margin() <= precision {
+                break;
+            }
+        }
+    }
+
+    estimate
+}
+
+// Shuffling makes queues that repeat pieces likelier than the rest, as
+// there are fewer distinct orders of them. Weighting them by this evens
+// that out: one over the ways of picking which of each type's pieces are
+// the extra ones.
+fn keep_chance(unknown: &[PieceType], extras: &[PieceType]) -> f64 {
+    PIECE_TYPES
+        .iter()
+        .map(|piece_type| {
+            let setup = unknown.iter().filter(|p| *p == piece_type).count();
+            let extra = extras.iter().filter(|p| *p == piece_type).count();
+            (1..=extra)
+                .map(|i| i as f64 / (setup + i) as f64)
+                .product::<f64>()
+        })
+        .product()
+}
@@ -1,6 +1,50 @@
-use crate::piece::Piece;
-use permutohedron::Heap;
+// Goes through every distinct ordering of the items once, in lexicographic
+// order. Items that are the same are interchangeable, so LLT only gives LLT,
+// LTL and TLL.
+pub struct PermIter<T> {
+    items: Vec<T>,
+    done: bool,
+}
+
+pub fn generate_perm_iter<T: Ord + Clone>(mut items: Vec<T>) -> PermIter<T> {
+    items.sort();
+    PermIter { items, done: false }
+}
+
+impl<T: Ord + Clone> Iterator for PermIter<T> {
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Vec<T>> {
+        if self.done {
+            return None;
+        }
+
+        let current = self.items.clone();
+        self.done = !next_permutation(&mut self.items);
+        Some(current)
+    }
+}
+
+// rearranges items into the next permutation, returns false after the last one
+fn next_permutation<T: Ord>(items: &mut [T]) -> bool {
+    if items.len() < 2 {
+        return false;
+    }
+
+    let mut i = items.len() - 1;
+    while i > 0 && items[i - 1] >= items[i] {
+        i -= 1;
+    }
+    if i == 0 {
+        return false;
+    }
+
+    let mut j = items.len() - 1;
+    while items[j] <= items[i - 1] {
+        j -= 1;
+    }
 
-pub fn generate_perm_iter<'a>(pieces: &'a mut Vec<Piece>) -> Heap<Vec<Piece>, Piece> {
-    Heap::new(pieces)
+    items.swap(i - 1, j);
+    items[i..].reverse();
+    true
 }
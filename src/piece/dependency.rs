@@ -0,0 +1,217 @@
+use super::{piece_block_positions, piece_in_region, Piece};
+use crate::field::{PercentageOptions, FLOOR_ROW};
+use crate::fieldmatrix::FieldMatrix;
+use std::fmt::Write;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Reason {
+    // the later piece rests on the earlier piece and nothing else
+    Support,
+    // the later piece is over one of the earlier piece's columns, so placing
+    // it first blocks the earlier piece's harddrop
+    Harddrop,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Dependency {
+    pub before: usize,
+    pub after: usize,
+    pub reason: Reason,
+}
+
+// Which pieces of a setup have to be placed before which, worked out from the
+// finished setup. Indexes are into the setup's pieces.
+pub struct PlacementGraph {
+    pub pieces: Vec<Piece>,
+    pub dependencies: Vec<Dependency>,
+    // the pieces each piece could rest on. None if it can rest on the floor,
+    // garbage or don't care cells instead.
+    pub supporters: Vec<Option<Vec<usize>>>,
+    // dependencies that have to hold with the given options
+    predecessors: Vec<Vec<usize>>,
+}
+
+impl PlacementGraph {
+    // Pieces inside the dont_care cells may end up anywhere, so they are left
+    // out of the graph and the dont_care cells can hold pieces up.
+    pub fn new(
+        pieces: &[Piece],
+        base_field: &FieldMatrix,
+        dont_care: &FieldMatrix,
+        options: PercentageOptions,
+    ) -> PlacementGraph {
+        let mut owners: [[Option<usize>; 10]; 24] = [[None; 10]; 24];
+        let positions = pieces
+            .iter()
+            .map(|piece| piece_block_positions(*piece).unwrap())
+            .collect::<Vec<Vec<(usize, usize)>>>();
+
+        for (i, piece) in pieces.iter().enumerate() {
+            if piece_in_region(*piece, dont_care) {
+                continue;
+            }
+            for (x, y) in positions[i].iter() {
+                owners[*y][*x] = Some(i);
+            }
+        }
+
+        let mut dependencies = Vec::new();
+        let mut supporters = Vec::new();
+
+        for (i, piece) in pieces.iter().enumerate() {
+            if piece_in_region(*piece, dont_care) {
+                supporters.push(None);
+                continue;
+            }
+
+            let mut grounded = false;
+            let mut below = Vec::new();
+            for (x, y) in positions[i].iter() {
+                if *y == FLOOR_ROW || base_field[*y + 1][*x] != 0 || dont_care[*y + 1][*x] != 0 {
+                    grounded = true;
+                    continue;
+                }
+                match owners[*y + 1][*x] {
+                    Some(j) if j != i && !below.contains(&j) => below.push(j),
+                    _ => (),
+                }
+            }
+
+            if !grounded && below.len() == 1 {
+                dependencies.push(Dependency {
+                    before: below[0],
+                    after: i,
+                    reason: Reason::Support,
+                });
+            }
+            supporters.push(if grounded { None } else { Some(below) });
+
+            // anything over this piece's blocks has to come after it
+            let mut above = Vec::new();
+            for (x, y) in positions[i].iter() {
+                for row in owners.iter().take(*y) {
+                    match row[*x] {
+                        Some(j) if j != i && !above.contains(&j) => above.push(j),
+                        _ => (),
+                    }
+                }
+            }
+            for j in above.into_iter() {
+                dependencies.push(Dependency {
+                    before: i,
+                    after: j,
+                    reason: Reason::Harddrop,
+                });
+            }
+        }
+
+        let mut predecessors = vec![Vec::new(); pieces.len()];
+        for dependency in dependencies.iter() {
+            // with softdrop a piece can be tucked in under another one
+            if dependency.reason == Reason::Harddrop && options.soft_drop {
+                continue;
+            }
+            if !predecessors[dependency.after].contains(&dependency.before) {
+                predecessors[dependency.after].push(dependency.before);
+            }
+        }
+
+        PlacementGraph {
+            pieces: pieces.to_vec(),
+            dependencies,
+            supporters,
+            predecessors,
+        }
+    }
+
+    // the pieces that have to be placed before this one
+    pub fn predecessors(&self, i: usize) -> &[usize] {
+        &self.predecessors[i]
+    }
+
+    fn can_place(&self, i: usize, placed: &[bool]) -> bool {
+        let supported = match &self.supporters[i] {
+            None => true,
+            Some(below) => below.iter().any(|j| placed[*j]),
+        };
+        supported && self.predecessors[i].iter().all(|j| placed[*j])
+    }
+
+    // An order that places every piece with something under it and after
+    // everything it depends on, if there is one. Placing a piece never makes
+    // another piece impossible, so taking any placeable piece is fine.
+    pub fn find_order(&self) -> Option<Vec<usize>> {
+        let mut placed = vec![false; self.pieces.len()];
+        let mut order = Vec::new();
+
+        while order.len() < self.pieces.len() {
+            let next =
+                (0..self.pieces.len()).find(|i| !placed[*i] && self.can_place(*i, &placed))?;
+            placed[next] = true;
+            order.push(next);
+        }
+
+        Some(order)
+    }
+
+    // whether a has to come before b, directly or through other pieces
+    pub fn comes_before(&self, a: usize, b: usize) -> bool {
+        let mut visited = vec![false; self.pieces.len()];
+        let mut stack = vec![b];
+
+        while let Some(i) = stack.pop() {
+            for j in self.predecessors[i].iter() {
+                if *j == a {
+                    return true;
+                }
+                if !visited[*j] {
+                    visited[*j] = true;
+                    stack.push(*j);
+                }
+            }
+        }
+
+        false
+    }
+
+    // pieces where everything they could rest on has to come after them
+    pub fn held_up_by_later_pieces(&self) -> Vec<usize> {
+        (0..self.pieces.len())
+            .filter(|i| match &self.supporters[*i] {
+                None => false,
+                Some(below) => !below.is_empty() && below.iter().all(|j| self.comes_before(*i, *j)),
+            })
+            .collect()
+    }
+
+    // Graphviz DOT for the graph, one node per piece. Support edges are solid
+    // and harddrop edges dashed.
+    pub fn to_dot(&self, name: &str) -> String {
+        let mut s = String::new();
+        writeln!(s, "digraph {} {{", name).unwrap();
+
+        for (i, piece) in self.pieces.iter().enumerate() {
+            writeln!(
+                s,
+                "    p{} [label=\"{} ({}, {})\"];",
+                i, piece.piece_type, piece.position.0, piece.position.1
+            )
+            .unwrap();
+        }
+        for dependency in self.dependencies.iter() {
+            let (label, style) = match dependency.reason {
+                Reason::Support => ("support", "solid"),
+                Reason::Harddrop => ("harddrop", "dashed"),
+            };
+            writeln!(
+                s,
+                "    p{} -> p{} [label=\"{}\", style={}];",
+                dependency.before, dependency.after, label, style
+            )
+            .unwrap();
+        }
+
+        s.push_str("}\n");
+        s
+    }
+}
@@ -0,0 +1,205 @@
+use super::lock_delay::LockState;
+use super::movement::{cells, drop_down, fits, rotate, Turn};
+use super::Piece;
+use crate::field::PercentageOptions;
+use crate::fieldmatrix::FieldMatrix;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Input {
+    // hold the key until the piece hits a wall or the stack
+    DasLeft,
+    DasRight,
+    Left,
+    Right,
+    Clockwise,
+    CounterClockwise,
+    HalfTurn,
+    // soft drop all the way down
+    SoftDrop,
+    HardDrop,
+}
+
+impl Display for Input {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Input::DasLeft => "DAS left",
+                Input::DasRight => "DAS right",
+                Input::Left => "left",
+                Input::Right => "right",
+                Input::Clockwise => "rotate CW",
+                Input::CounterClockwise => "rotate CCW",
+                Input::HalfTurn => "rotate 180",
+                Input::SoftDrop => "soft drop",
+                Input::HardDrop => "hard drop",
+            }
+        )
+    }
+}
+
+pub fn format_inputs(inputs: &[Input]) -> String {
+    inputs
+        .iter()
+        .map(|input| input.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// at 20G the piece falls off any ledge it gets moved over
+fn land(piece: Piece, field: &FieldMatrix, options: PercentageOptions) -> Piece {
+    if options.gravity.is_twenty_g() {
+        drop_down(piece, field)
+    } else {
+        piece
+    }
+}
+
+// every cell the piece slides over on the ground uses up a move reset
+fn das(
+    piece: Piece,
+    state: LockState,
+    dx: isize,
+    field: &FieldMatrix,
+    options: PercentageOptions,
+) -> Option<(Piece, LockState)> {
+    let (mut moved, mut state) = (piece, state);
+    while fits(moved.clone_with_offset(dx, 0), field) {
+        let next = land(moved.clone_with_offset(dx, 0), field, options);
+        state = options.lock_delay.after_move(state, moved, next, field)?;
+        moved = next;
+    }
+    if moved.position == piece.position {
+        return None;
+    }
+    Some((moved, state))
+}
+
+// where an input takes the piece, if it moves it at all before it locks
+fn apply(
+    input: Input,
+    piece: Piece,
+    state: LockState,
+    field: &FieldMatrix,
+    options: PercentageOptions,
+) -> Option<(Piece, LockState)> {
+    let system = options.rotation_system;
+    let moved = match input {
+        Input::DasLeft => return das(piece, state, -1, field, options),
+        Input::DasRight => return das(piece, state, 1, field, options),
+        Input::Left => piece.clone_with_offset(-1, 0),
+        Input::Right => piece.clone_with_offset(1, 0),
+        Input::Clockwise => rotate(piece, Turn::Clockwise, field, system)?.0,
+        Input::CounterClockwise => rotate(piece, Turn::CounterClockwise, field, system)?.0,
+        Input::HalfTurn => rotate(piece, Turn::Half, field, system)?.0,
+        Input::SoftDrop => drop_down(piece, field),
+        Input::HardDrop => return None,
+    };
+
+    if (moved.position == piece.position && moved.rotation == piece.rotation) || !fits(moved, field)
+    {
+        return None;
+    }
+    let landed = land(moved, field, options);
+    let state = options.lock_delay.after_move(state, piece, landed, field)?;
+    Some((landed, state))
+}
+
+fn turn_of(input: Input) -> Option<Turn> {
+    match input {
+        Input::Clockwise => Some(Turn::Clockwise),
+        Input::CounterClockwise => Some(Turn::CounterClockwise),
+        Input::HalfTurn => Some(Turn::Half),
+        _ => None,
+    }
+}
+
+// The fewest inputs that take a piece from its spawn to the target and lock
+// it there with a hard drop. Every input counts as one key press, DAS
+// included. Without das the piece can only be tapped over, and soft drop
+// always goes all the way down. At 20G the piece lands after every input,
+// and with irs the first turn can be made before it lands. Moves on the
+// ground are limited by the lock delay like in the placement search.
+pub fn finesse(
+    target: Piece,
+    field: &FieldMatrix,
+    options: PercentageOptions,
+) -> Option<Vec<Input>> {
+    let target_cells = cells(target);
+    let start = options.rotation_system.spawn_piece(target.piece_type);
+    if !fits(start, field) {
+        return None;
+    }
+
+    let mut inputs = vec![
+        Input::Left,
+        Input::Right,
+        Input::Clockwise,
+        Input::CounterClockwise,
+    ];
+    if options.das {
+        inputs.extend([Input::DasLeft, Input::DasRight].iter());
+    }
+    if options.rotation_system.has_half_turns() {
+        inputs.push(Input::HalfTurn);
+    }
+    if options.soft_drop && !options.gravity.is_twenty_g() {
+        inputs.push(Input::SoftDrop);
+    }
+
+    // how each state was first reached, for walking the inputs back
+    let key = |piece: Piece, state: LockState| (piece.position, piece.rotation as usize, state);
+    let mut came_from = HashMap::new();
+    let landed = land(start, field, options);
+    let landed_state = options.lock_delay.start(landed);
+    came_from.insert(key(landed, landed_state), None);
+    let mut queue = VecDeque::new();
+    queue.push_back((landed, landed_state));
+
+    // with irs a turn held before the piece appears turns it unkicked
+    if options.gravity.initial_rotation() {
+        for input in inputs.iter() {
+            let turned = turn_of(*input)
+                .and_then(|turn| rotate(start, turn, field, options.rotation_system))
+                .filter(|(_, kick)| *kick == 0);
+            if let Some((turned, _)) = turned {
+                let turned = land(turned, field, options);
+                let turned_state = options.lock_delay.start(turned);
+                if let Entry::Vacant(entry) = came_from.entry(key(turned, turned_state)) {
+                    entry.insert(Some((landed, landed_state, *input)));
+                    queue.push_back((turned, turned_state));
+                }
+            }
+        }
+    }
+
+    while let Some((current, state)) = queue.pop_front() {
+        if cells(drop_down(current, field)) == target_cells {
+            let mut path = vec![Input::HardDrop];
+            let (mut piece, mut piece_state) = (current, state);
+            while let Some((previous, previous_state, input)) = came_from[&key(piece, piece_state)]
+            {
+                path.push(input);
+                piece = previous;
+                piece_state = previous_state;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        for input in inputs.iter() {
+            if let Some((next, next_state)) = apply(*input, current, state, field, options) {
+                if let Entry::Vacant(entry) = came_from.entry(key(next, next_state)) {
+                    entry.insert(Some((current, state, *input)));
+                    queue.push_back((next, next_state));
+                }
+            }
+        }
+    }
+
+    None
+}
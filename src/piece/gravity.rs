@@ -0,0 +1,32 @@
+// How fast pieces fall. At 20G a piece drops to the stack the moment it
+// spawns and after every move, so it can't be tucked under overhangs and
+// has to be kicked up to get over anything in its way.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Gravity {
+    // slow enough to move the piece anywhere before it falls
+    Normal,
+    // irs lets the rotation key turn the piece before it appears. ihs lets
+    // the hold key swap it before it appears, which is the only way to hold
+    // at 20G here
+    TwentyG { irs: bool, ihs: bool },
+}
+
+impl Gravity {
+    pub fn is_twenty_g(self) -> bool {
+        self != Gravity::Normal
+    }
+
+    pub fn initial_rotation(self) -> bool {
+        match self {
+            Gravity::Normal => false,
+            Gravity::TwentyG { irs, .. } => irs,
+        }
+    }
+
+    pub fn allows_hold(self) -> bool {
+        match self {
+            Gravity::Normal => true,
+            Gravity::TwentyG { ihs, .. } => ihs,
+        }
+    }
+}
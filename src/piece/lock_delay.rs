@@ -0,0 +1,79 @@
+use super::{piece_block_positions, piece_fits_over, Piece};
+use crate::fieldmatrix::FieldMatrix;
+
+// How long a piece can be moved around once it touches the ground. Every
+// move or rotation made while the piece is on the ground resets the lock
+// timer and uses up one of the move resets; once they're gone the piece
+// locks as soon as it touches the ground. With step reset the count starts
+// over whenever the piece falls below the lowest row it has been on.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LockDelay {
+    // the piece never locks on its own
+    Infinite,
+    MoveReset { limit: usize, step_reset: bool },
+}
+
+// what the search has to remember about the lock timer along the way
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct LockState {
+    pub resets: usize,
+    pub lowest: usize,
+}
+
+fn grounded(piece: Piece, field: &FieldMatrix) -> bool {
+    !piece_fits_over(piece.clone_with_offset(0, 1), 0, field).unwrap_or_default()
+}
+
+fn lowest_row(piece: Piece) -> usize {
+    piece_block_positions(piece)
+        .map(|positions| positions.iter().map(|(_, y)| *y).max().unwrap_or(0))
+        .unwrap_or(0)
+}
+
+impl LockDelay {
+    pub fn start(self, piece: Piece) -> LockState {
+        match self {
+            LockDelay::MoveReset {
+                step_reset: true, ..
+            } => LockState {
+                resets: 0,
+                lowest: lowest_row(piece),
+            },
+            _ => LockState {
+                resets: 0,
+                lowest: 0,
+            },
+        }
+    }
+
+    // The lock timer after moving the piece from one spot to the next, or
+    // None if the piece would have locked before it got to move.
+    pub fn after_move(
+        self,
+        state: LockState,
+        from: Piece,
+        to: Piece,
+        field: &FieldMatrix,
+    ) -> Option<LockState> {
+        let (limit, step_reset) = match self {
+            // nothing to keep track of, so the search doesn't grow
+            LockDelay::Infinite => return Some(state),
+            LockDelay::MoveReset { limit, step_reset } => (limit, step_reset),
+        };
+
+        let mut state = state;
+        if grounded(from, field) {
+            if state.resets >= limit {
+                return None;
+            }
+            state.resets += 1;
+        }
+
+        let lowest = lowest_row(to);
+        if step_reset && lowest > state.lowest {
+            state.lowest = lowest;
+            state.resets = 0;
+        }
+        Some(state)
+    }
+}
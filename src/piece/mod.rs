@@ -1,9 +1,19 @@
-use crate::field::{PercentageOptions, BOTTOM_ROW_DISCARD_COUNT};
+use crate::field::{PercentageOptions, FLOOR_ROW};
 use crate::fieldmatrix::FieldMatrix;
+use crate::tiling::tile_region;
 use std::fmt::{self, Display, Formatter, Write};
 
+pub mod dependency;
+pub mod finesse;
+pub mod gravity;
+pub mod lock_delay;
+pub mod movement;
 pub mod piece_col;
+pub mod rotation_system;
+pub mod ruleset;
+pub mod spawn;
 use piece_col::{PieceCollision, I, J, L, O, S, T, Z};
+use rotation_system::RotationSystem;
 
 pub fn piece_type_to_fumen_index(piece: PieceType) -> u8 {
     match piece {
@@ -30,7 +40,7 @@ pub fn fumen_index_to_piece_type(i: u8) -> Result<PieceType, &'static str> {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub enum PieceType {
     S,
     Z,
@@ -41,6 +51,16 @@ pub enum PieceType {
     I,
 }
 
+pub const PIECE_TYPES: [PieceType; 7] = [
+    PieceType::S,
+    PieceType::Z,
+    PieceType::L,
+    PieceType::J,
+    PieceType::T,
+    PieceType::O,
+    PieceType::I,
+];
+
 impl Display for PieceType {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(
@@ -59,6 +79,42 @@ impl Display for PieceType {
     }
 }
 
+impl PieceType {
+    pub fn mirror(self) -> PieceType {
+        match self {
+            PieceType::S => PieceType::Z,
+            PieceType::Z => PieceType::S,
+            PieceType::L => PieceType::J,
+            PieceType::J => PieceType::L,
+            other => other,
+        }
+    }
+}
+
+pub fn char_to_piece_type(c: char) -> Result<PieceType, String> {
+    match c.to_ascii_uppercase() {
+        'S' => Ok(PieceType::S),
+        'Z' => Ok(PieceType::Z),
+        'L' => Ok(PieceType::L),
+        'J' => Ok(PieceType::J),
+        'T' => Ok(PieceType::T),
+        'O' => Ok(PieceType::O),
+        'I' => Ok(PieceType::I),
+        _ => Err(format!("'{}' is not a piece", c)),
+    }
+}
+
+pub fn parse_queue(queue: &str) -> Result<Vec<PieceType>, String> {
+    queue.chars().map(char_to_piece_type).collect()
+}
+
+pub fn mirror_queue(queue: &str) -> Result<String, String> {
+    Ok(parse_queue(queue)?
+        .into_iter()
+        .map(|piece_type| piece_type.mirror().to_string())
+        .collect())
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Rotation {
     Normal,
@@ -67,6 +123,16 @@ pub enum Rotation {
     Double,
 }
 
+impl Rotation {
+    pub fn mirror(self) -> Rotation {
+        match self {
+            Rotation::Right => Rotation::Left,
+            Rotation::Left => Rotation::Right,
+            other => other,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct Piece {
     pub piece_type: PieceType,
@@ -89,15 +155,77 @@ impl Piece {
         piece
     }
 
+    // positive dy moves the piece down the field
     pub fn clone_with_offset(&self, dx: isize, dy: isize) -> Piece {
         let (x, y) = self.position;
-        let mut new = self.clone();
-        new.position = (x - dx, y - dy);
+        let mut new = *self;
+        new.position = (x + dx, y + dy);
         new
     }
+
+    // the same piece flipped horizontally across the field. Rotations happen
+    // around the center of the collision box so the box just moves across.
+    pub fn mirror(&self) -> Piece {
+        let (x, y) = self.position;
+        let size = self.collision().size as isize;
+        Piece {
+            piece_type: self.piece_type.mirror(),
+            rotation: self.rotation.mirror(),
+            position: (10 - size - x, y),
+        }
+    }
+}
+
+pub fn color_field_to_pieces(field: FieldMatrix) -> Result<Vec<Piece>, String> {
+    let piece_possibilities = find_piece_possibilities(&field)?;
+
+    // parse piece possibilies for overlapping pieces
+    let ambiguous_points = find_ambiguous_points(&piece_possibilities);
+    if !ambiguous_points.is_empty() {
+        return Err(format_points(&ambiguous_points, "Ambiguous points at:\n"));
+    }
+
+    // points with no piece covering
+    let unused_points = find_unused_points(&piece_possibilities, &field);
+    if !unused_points.is_empty() {
+        return Err(format_points(&unused_points, "Unused points at:\n"));
+    }
+
+    Ok(piece_possibilities)
+}
+
+// Like color_field_to_pieces, but where pieces of the same color overlap every
+// way of splitting the blocks into pieces is returned as a separate setup.
+pub fn color_field_to_setups(field: FieldMatrix) -> Result<Vec<Vec<Piece>>, String> {
+    let piece_possibilities = find_piece_possibilities(&field)?;
+
+    let ambiguous_points = find_ambiguous_points(&piece_possibilities);
+    if ambiguous_points.is_empty() {
+        return color_field_to_pieces(field).map(|pieces| vec![pieces]);
+    }
+
+    let unused_points = find_unused_points(&piece_possibilities, &field);
+    if !unused_points.is_empty() {
+        return Err(format_points(&unused_points, "Unused points at:\n"));
+    }
+
+    let mut region = field;
+    for n in region.iter_mut().flatten().filter(|n| **n != 0) {
+        *n = 1;
+    }
+
+    let setups = tile_region(&region, &piece_possibilities, None);
+    if setups.is_empty() {
+        return Err(format_points(
+            &ambiguous_points,
+            "No way to split the ambiguous points into pieces at:\n",
+        ));
+    }
+
+    Ok(setups)
 }
 
-pub fn color_field_to_pieces<'a>(field: FieldMatrix) -> Result<Vec<Piece>, String> {
+fn find_piece_possibilities(field: &FieldMatrix) -> Result<Vec<Piece>, String> {
     let mut piece_possibilities = Vec::new();
     let rotations = [
         Rotation::Normal,
@@ -125,40 +253,25 @@ pub fn color_field_to_pieces<'a>(field: FieldMatrix) -> Result<Vec<Piece>, Strin
             // unique iterations
             for rot in rotations.iter().take(get_rotation_times(piece_type)) {
                 piece.rotation = *rot;
-                if piece_fits_over(piece, *fumen_index, &field).unwrap_or_else(|| false) {
-                    piece_possibilities.push(piece.clone());
+                if piece_fits_over(piece, *fumen_index, field).unwrap_or(false) {
+                    piece_possibilities.push(piece);
                 }
             }
         }
     }
 
-    // parse piece possibilies for overlapping pieces
-    let ambiguous_points = find_ambiguous_points(&piece_possibilities);
-    if ambiguous_points.len() > 0 {
-        let mut error_string = String::from("Ambiguous points at:\n");
-        for point in ambiguous_points.iter() {
-            let (x, y) = point;
-            writeln!(error_string, "x: {}, y: {}", x, y).unwrap();
-        }
-        return Err(error_string);
-    }
-
-    // points with no piece covering
-    let unused_points = find_unused_points(&piece_possibilities, &field);
-    if unused_points.len() > 0 {
-        let mut error_string = String::from("Unused points at:\n");
+    Ok(piece_possibilities)
+}
 
-        for point in unused_points.iter() {
-            let (x, y) = point;
-            writeln!(error_string, "x: {}, y: {}", x, y).unwrap();
-        }
-        return Err(error_string);
+fn format_points(points: &[(usize, usize)], init_err_str: &str) -> String {
+    let mut error_string = String::from(init_err_str);
+    for (x, y) in points.iter() {
+        writeln!(error_string, "x: {}, y: {}", x, y).unwrap();
     }
-
-    Ok(piece_possibilities)
+    error_string
 }
 
-fn find_ambiguous_points(pieces: &Vec<Piece>) -> Vec<(usize, usize)> {
+fn find_ambiguous_points(pieces: &[Piece]) -> Vec<(usize, usize)> {
     let mut temp_field = [[0; 10]; 24];
     for piece in pieces.iter() {
         for (x, y) in piece_block_positions(*piece).unwrap().iter() {
@@ -175,8 +288,8 @@ fn find_ambiguous_points(pieces: &Vec<Piece>) -> Vec<(usize, usize)> {
         .collect::<Vec<(usize, usize)>>()
 }
 
-pub fn find_unused_points(pieces: &Vec<Piece>, field: &FieldMatrix) -> Vec<(usize, usize)> {
-    let mut test_field = field.clone();
+pub fn find_unused_points(pieces: &[Piece], field: &FieldMatrix) -> Vec<(usize, usize)> {
+    let mut test_field = *field;
 
     // get rid of grey blocks
     for n in test_field.iter_mut().flatten().filter(|n| **n == 1) {
@@ -227,6 +340,46 @@ pub fn piece_fits_over(piece: Piece, cover_type: u8, field: &FieldMatrix) -> Opt
     Some(true)
 }
 
+// region is a mask where the cells of the region are 1
+pub fn piece_in_region(piece: Piece, region: &FieldMatrix) -> bool {
+    piece_fits_over(piece, 1, region).unwrap_or(false)
+}
+
+// every placement of a piece type lying completely inside a region, worked
+// out one at a time so a search can stop at the first one that does
+pub fn placements_in_region<'a>(
+    piece_type: PieceType,
+    field: &'a FieldMatrix,
+    region: &'a FieldMatrix,
+    options: PercentageOptions<'a>,
+) -> impl Iterator<Item = Piece> + 'a {
+    const ROTATIONS: [Rotation; 4] = [
+        Rotation::Normal,
+        Rotation::Right,
+        Rotation::Double,
+        Rotation::Left,
+    ];
+    let rotation_times = get_rotation_times(piece_type);
+
+    (-3..24)
+        .flat_map(move |y| {
+            (-3..10).flat_map(move |x| {
+                ROTATIONS[..rotation_times]
+                    .iter()
+                    .map(move |rotation| Piece {
+                        piece_type,
+                        rotation: *rotation,
+                        position: (x, y),
+                    })
+            })
+        })
+        .filter(move |piece| {
+            piece_in_region(*piece, region)
+                && piece_fits_over(*piece, 0, field).unwrap_or(false)
+                && piece_can_be_placed(*piece, field, options)
+        })
+}
+
 pub fn piece_block_positions(piece: Piece) -> Option<Vec<(usize, usize)>> {
     let col = piece.collision();
     let size = col.size as isize;
@@ -245,7 +398,7 @@ pub fn piece_block_positions(piece: Piece) -> Option<Vec<(usize, usize)>> {
     Some(positions)
 }
 
-fn get_rotation_times(piece: PieceType) -> usize {
+pub fn get_rotation_times(piece: PieceType) -> usize {
     match piece {
         PieceType::O => 1,
         PieceType::S | PieceType::Z | PieceType::I => 2,
@@ -258,159 +411,67 @@ pub fn piece_can_be_placed(
     base_field: &FieldMatrix,
     options: PercentageOptions,
 ) -> bool {
-    if empty_below_piece(piece, &base_field) {
+    if empty_below_piece(piece, base_field) || spawn::locked_out(piece) {
         return false;
     }
-
-    if can_harddrop(piece, &base_field) {
-        return true;
-    }
-    if options.soft_drop {
-        softdrop_stem_check(piece, &base_field);
+    if !piece_reachable(piece, base_field, options) {
+        return false;
     }
 
-    false
+    // too slow if it takes more inputs than allowed, not counting the hard drop
+    match options.max_inputs {
+        Some(max_inputs) => finesse::finesse(piece, base_field, options)
+            .is_some_and(|inputs| inputs.len() <= max_inputs + 1),
+        None => true,
+    }
 }
 
-fn softdrop_stem_check(base_piece: Piece, base_field: &FieldMatrix) -> bool {
-    let mut left_positions: Vec<Piece> = Vec::new();
-    let mut right_positions: Vec<Piece> = Vec::new();
-    let mut stem_positions: Vec<Piece> = Vec::new();
-    stem_positions.push(base_piece);
-    //let mut spin_positions: Vec<Piece> = Vec::new();
-
-    let mut test_piece_left = |prev_piece: Piece| -> Option<(bool, Piece)> {
-        let test_piece = prev_piece.clone_with_offset(-1, 0);
-
-        if piece_fits_over(test_piece, 0, &base_field).unwrap_or_default() {
-            if can_harddrop(test_piece, &base_field) {
-                return Some((true, test_piece));
-            }
-            left_positions.push(test_piece);
-            return Some((false, test_piece));
-        }
-        None
-    };
-
-    let mut test_piece_right = |prev_piece: Piece| -> Option<(bool, Piece)> {
-        let test_piece = prev_piece.clone_with_offset(1, 0);
-
-        if piece_fits_over(test_piece, 0, &base_field).unwrap_or_default() {
-            if can_harddrop(test_piece, &base_field) {
-                return Some((true, test_piece));
-            }
-            right_positions.push(test_piece);
-            return Some((false, test_piece));
-        }
-        None
-    };
-
-    let test_piece_up = |prev_piece: Piece| -> (bool, Piece) {
-        let test_piece = prev_piece.clone_with_offset(0, 1);
-        (
-            piece_fits_over(test_piece, 0, &base_field).unwrap_or_default(),
-            test_piece,
+fn piece_reachable(piece: Piece, base_field: &FieldMatrix, options: PercentageOptions) -> bool {
+    let search = || {
+        movement::placement_spin(
+            piece,
+            base_field,
+            options.soft_drop,
+            options.rotation_system,
+            options.lock_delay,
+            options.gravity,
         )
+        .is_some()
     };
 
-    let mut add_piece_up = |prev_piece: Piece| -> (bool, Piece) {
-        let test_piece = prev_piece.clone_with_offset(0, 1);
-
-        if piece_fits_over(test_piece, 0, &base_field).unwrap_or_default() {
-            stem_positions.push(test_piece);
-
-            return (true, test_piece);
-        }
-        (false, test_piece)
-    };
-
-    // add all pieces up from the base piece to stem_positions until blocked
-    // add_piece_up pushes to stem_positions
-    let mut up_piece = base_piece;
-
-    loop {
-        let (was_added, new_up_piece) = add_piece_up(up_piece);
-        if !was_added {
-            break;
-        }
-
-        up_piece = new_up_piece;
+    // at 20G pieces only get anywhere by moving and turning on the stack
+    if options.gravity.is_twenty_g() {
+        return search();
     }
 
-    // for each stem position push out left and right
-    // if the position can be harddropped then return true
-    // if the position can go up make another stem
-    // else continue going left and right until blocked
-    for stem_piece in stem_positions.iter() {
-        let mut left_piece = *stem_piece;
-        while let Some((works, new_left_piece)) = test_piece_left(left_piece) {
-            if works {
-                return true;
-            }
-
-            // if a new stem can be created do so
-            let (make_stem, new_stem_piece) = test_piece_up(new_left_piece);
-            if make_stem {
-                if softdrop_stem_check(new_stem_piece, &base_field) {
-                    return true;
-                }
-            }
-
-            left_piece = new_left_piece;
-        }
-
-        let mut right_piece = *stem_piece;
-        while let Some((works, new_right_piece)) = test_piece_right(right_piece) {
-            if works {
-                return true;
-            }
-
-            // if a new stem can be created do so
-            let (make_stem, new_stem_piece) = test_piece_up(new_right_piece);
-            if make_stem {
-                if softdrop_stem_check(new_stem_piece, &base_field) {
-                    return true;
-                }
-            }
-
-            right_piece = new_right_piece;
-        }
+    if can_harddrop(piece, base_field, options.rotation_system) {
+        return true;
     }
-
-    false
-}
-
-// maybe remove semi-redundant empty below piece check in can_harddrop
-pub fn can_harddrop(piece: Piece, field: &FieldMatrix) -> bool {
-    let mut empty_space_below_piece = true;
-
-    for (x, y) in piece_block_positions(piece).unwrap().iter() {
-        // if colummn above piece has no blockage
-        if field
-            .iter()
-            .map(|row| row[*x])
-            .take(*y) // fix/test
-            .any(|n| n != 0)
-        {
-            return false;
-        }
-        if *y == 23 - BOTTOM_ROW_DISCARD_COUNT || field[*y + 1][*x] != 0 {
-            empty_space_below_piece = false
-        }
+    if options.soft_drop
+        && spawn::softdrop_from_spawn(
+            piece,
+            base_field,
+            options.rotation_system,
+            options.lock_delay,
+        )
+    {
+        return true;
     }
 
-    if empty_space_below_piece {
-        return false;
-    }
+    // the search finds everything a drop can reach too, so it's only run
+    // for the placements that need a spin
+    options.spin && search()
+}
 
-    true
+pub fn can_harddrop(piece: Piece, field: &FieldMatrix, system: RotationSystem) -> bool {
+    spawn::harddrop_from_spawn(piece, field, system)
 }
 
 fn empty_below_piece(piece: Piece, field: &FieldMatrix) -> bool {
     let mut empty_space_below_piece = true;
 
     for (x, y) in piece_block_positions(piece).unwrap().iter() {
-        if *y == 23 - BOTTOM_ROW_DISCARD_COUNT || field[*y + 1][*x] != 0 {
+        if *y == FLOOR_ROW || field[*y + 1][*x] != 0 {
             empty_space_below_piece = false;
             break;
         }
@@ -450,14 +511,14 @@ fn piece_check_offset(piece_type: PieceType) -> (isize, isize) {
     }
 }
 
-pub fn impossibilites(pieces: &Vec<Piece>, full_field: &FieldMatrix) -> Vec<Piece> {
+pub fn impossibilites(pieces: &[Piece], full_field: &FieldMatrix) -> Vec<Piece> {
     let mut impossible_pieces = Vec::new();
 
     for piece in pieces.iter() {
         let mut piece_supported = false;
         let block_positions = piece_block_positions(*piece).unwrap();
         for (x, y) in block_positions.iter() {
-            if *y == 23 - BOTTOM_ROW_DISCARD_COUNT {
+            if *y == FLOOR_ROW {
                 // on ground
                 piece_supported = true;
                 break;
@@ -477,7 +538,7 @@ pub fn impossibilites(pieces: &Vec<Piece>, full_field: &FieldMatrix) -> Vec<Piec
                 }
             }
         }
-        if !piece_supported {
+        if !piece_supported || spawn::locked_out(*piece) {
             impossible_pieces.push(*piece);
         }
         // todo more impossible checks
@@ -486,7 +547,28 @@ pub fn impossibilites(pieces: &Vec<Piece>, full_field: &FieldMatrix) -> Vec<Piec
     impossible_pieces
 }
 
-pub fn format_pieces<'a>(pieces: &Vec<Piece>, init_err_str: &'a str) -> String {
+// a piece of a build order, with its spin and the inputs that place it
+pub type Placement = (Piece, movement::Spin, Option<Vec<finesse::Input>>);
+
+pub fn format_placements(placements: &[Placement], init_str: &str) -> String {
+    let mut s = String::from(init_str);
+
+    for (piece, spin, inputs) in placements.iter() {
+        let inputs = match inputs {
+            Some(inputs) => finesse::format_inputs(inputs),
+            None => String::from("no inputs found"),
+        };
+        writeln!(
+            s,
+            "piece: {}, position: {}, {}, {}, inputs: {}",
+            piece.piece_type, piece.position.0, piece.position.1, spin, inputs,
+        )
+        .unwrap();
+    }
+    s
+}
+
+pub fn format_pieces(pieces: &[Piece], init_err_str: &str) -> String {
     let mut s = String::from(init_err_str);
 
     for piece in pieces.iter() {
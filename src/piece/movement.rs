@@ -0,0 +1,301 @@
+use super::gravity::Gravity;
+use super::lock_delay::LockDelay;
+use super::rotation_system::RotationSystem;
+use super::{piece_block_positions, piece_fits_over, spawn, Piece, PieceType, Rotation};
+use crate::fieldmatrix::FieldMatrix;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+// Ordered from least to most special, a placement reached in several ways
+// keeps the best one.
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum Spin {
+    NotSpun,
+    // any other piece rotated into a spot it can't move out of
+    Immobile,
+    Mini,
+    Full,
+}
+
+impl Display for Spin {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Spin::NotSpun => "no spin",
+                Spin::Immobile => "spin",
+                Spin::Mini => "T-spin mini",
+                Spin::Full => "T-spin",
+            }
+        )
+    }
+}
+
+impl Rotation {
+    pub fn clockwise(self) -> Rotation {
+        match self {
+            Rotation::Normal => Rotation::Right,
+            Rotation::Right => Rotation::Double,
+            Rotation::Double => Rotation::Left,
+            Rotation::Left => Rotation::Normal,
+        }
+    }
+
+    pub fn counter_clockwise(self) -> Rotation {
+        match self {
+            Rotation::Normal => Rotation::Left,
+            Rotation::Left => Rotation::Double,
+            Rotation::Double => Rotation::Right,
+            Rotation::Right => Rotation::Normal,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Turn {
+    Clockwise,
+    CounterClockwise,
+    Half,
+}
+
+pub fn fits(piece: Piece, field: &FieldMatrix) -> bool {
+    piece_fits_over(piece, 0, field).unwrap_or_default()
+}
+
+// Rotates the piece with the rotation system, returning where it ended up
+// and which kick got it there.
+pub fn rotate(
+    piece: Piece,
+    turn: Turn,
+    field: &FieldMatrix,
+    system: RotationSystem,
+) -> Option<(Piece, usize)> {
+    let to = match turn {
+        Turn::Clockwise => piece.rotation.clockwise(),
+        Turn::CounterClockwise => piece.rotation.counter_clockwise(),
+        Turn::Half => piece.rotation.clockwise().clockwise(),
+    };
+
+    // the system's box stays where it is, the SRS box moves with the state
+    let (from_x, from_y) = system.state_offset(piece.piece_type, piece.rotation);
+    let (to_x, to_y) = system.state_offset(piece.piece_type, to);
+    let rotated = Piece {
+        rotation: to,
+        ..piece
+    }
+    .clone_with_offset(to_x - from_x, to_y - from_y);
+
+    let kicks = system.kicks(piece.piece_type, piece.rotation, to);
+    for (i, (dx, dy)) in kicks.iter().enumerate() {
+        if i == 1 && !system.kick_allowed(rotated, field) {
+            return None;
+        }
+        let kicked = rotated.clone_with_offset(*dx, -*dy);
+        if fits(kicked, field) {
+            return Some((kicked, i));
+        }
+    }
+
+    None
+}
+
+fn filled(field: &FieldMatrix, x: isize, y: isize) -> bool {
+    x < 0 || y < 0 || x >= 10 || y >= 24 || field[y as usize][x as usize] != 0
+}
+
+// What a piece that just got rotated into place counts as. T-spins use the
+// 3 corner rule: 3 of the corners around the T's center have to be filled,
+// and it's a mini unless both corners the T points towards are filled or
+// the kick used always gives a full T-spin.
+pub fn classify_spin(piece: Piece, full_kick: bool, field: &FieldMatrix) -> Spin {
+    if piece.piece_type != PieceType::T {
+        let immobile = [(-1, 0), (1, 0), (0, -1)]
+            .iter()
+            .all(|(dx, dy)| !fits(piece.clone_with_offset(*dx, *dy), field));
+        return if immobile {
+            Spin::Immobile
+        } else {
+            Spin::NotSpun
+        };
+    }
+
+    let (x, y) = piece.position;
+    let (cx, cy) = (x + 1, y + 1);
+    let corners = [(-1, -1), (1, -1), (1, 1), (-1, 1)];
+    let filled_corners = corners
+        .iter()
+        .filter(|(dx, dy)| filled(field, cx + dx, cy + dy))
+        .count();
+    if filled_corners < 3 {
+        return Spin::NotSpun;
+    }
+
+    let front = match piece.rotation {
+        Rotation::Normal => [corners[0], corners[1]],
+        Rotation::Right => [corners[1], corners[2]],
+        Rotation::Double => [corners[2], corners[3]],
+        Rotation::Left => [corners[3], corners[0]],
+    };
+    if full_kick || front.iter().all(|(dx, dy)| filled(field, cx + dx, cy + dy)) {
+        Spin::Full
+    } else {
+        Spin::Mini
+    }
+}
+
+pub fn cells(piece: Piece) -> Vec<(usize, usize)> {
+    let mut cells = piece_block_positions(piece).unwrap();
+    cells.sort_unstable();
+    cells
+}
+
+pub fn drop_down(piece: Piece, field: &FieldMatrix) -> Piece {
+    let mut piece = piece;
+    while fits(piece.clone_with_offset(0, 1), field) {
+        piece = piece.clone_with_offset(0, 1);
+    }
+    piece
+}
+
+// Every spot the piece can lock in when it starts at its spawn and gets
+// moved and rotated with the rotation system, with the best spin each spot
+// can be reached with. Without softdrop the piece is only moved at the
+// height it spawned at (kicks aside) and then harddropped. Moves on the
+// ground are limited by the lock delay. At 20G the piece lands right after
+// it spawns and after every move.
+pub fn reachable_placements(
+    piece_type: PieceType,
+    field: &FieldMatrix,
+    soft_drop: bool,
+    system: RotationSystem,
+    lock_delay: LockDelay,
+    gravity: Gravity,
+) -> Vec<(Piece, Spin)> {
+    let mut placements: HashMap<Vec<(usize, usize)>, (Piece, Spin)> = HashMap::new();
+    let start = system.spawn_piece(piece_type);
+    if !fits(start, field) {
+        return Vec::new();
+    }
+
+    let land = |piece: Piece| {
+        if gravity.is_twenty_g() {
+            drop_down(piece, field)
+        } else {
+            piece
+        }
+    };
+
+    let mut add_placement = |piece: Piece, spin: Spin| {
+        if spawn::locked_out(piece) {
+            return;
+        }
+        let entry = placements.entry(cells(piece)).or_insert((piece, spin));
+        if spin > entry.1 {
+            *entry = (piece, spin);
+        }
+    };
+
+    let mut turns = vec![Turn::Clockwise, Turn::CounterClockwise];
+    if system.has_half_turns() {
+        turns.push(Turn::Half);
+    }
+
+    // with irs the piece can also appear already turned, if it fits unkicked
+    let mut starts = vec![start];
+    if gravity.initial_rotation() {
+        starts.extend(
+            turns
+                .iter()
+                .filter_map(|turn| rotate(start, *turn, field, system))
+                .filter(|(_, kick)| *kick == 0)
+                .map(|(piece, _)| piece),
+        );
+    }
+
+    let mut visited = HashSet::new();
+    let mut stack = Vec::new();
+    for start in starts.into_iter().map(land) {
+        let start_state = lock_delay.start(start);
+        if visited.insert((start.position, start.rotation as usize, start_state)) {
+            stack.push((start, start_state));
+        }
+    }
+
+    while let Some((current, state)) = stack.pop() {
+        let mut moves = vec![
+            Some((current.clone_with_offset(-1, 0), None)),
+            Some((current.clone_with_offset(1, 0), None)),
+        ];
+        if soft_drop {
+            moves.push(Some((current.clone_with_offset(0, 1), None)));
+        }
+        for turn in turns.iter() {
+            moves.push(
+                rotate(current, *turn, field, system)
+                    .map(|(p, kick)| (p, Some(system.upgrades_spin(kick, *turn == Turn::Half)))),
+            );
+        }
+
+        let dropped = drop_down(current, field);
+        add_placement(dropped, Spin::NotSpun);
+
+        for (next, full_kick) in moves.into_iter().flatten() {
+            if !fits(next, field) {
+                continue;
+            }
+            let landed = land(next);
+            let next_state = match lock_delay.after_move(state, current, landed, field) {
+                Some(next_state) => next_state,
+                None => continue,
+            };
+            if let Some(full_kick) = full_kick {
+                // a rotation straight into a resting spot can lock as a spin
+                if !fits(next.clone_with_offset(0, 1), field) {
+                    add_placement(next, classify_spin(next, full_kick, field));
+                }
+            }
+            let next = landed;
+            if visited.insert((next.position, next.rotation as usize, next_state)) {
+                stack.push((next, next_state));
+            }
+        }
+    }
+
+    placements.into_values().collect()
+}
+
+// the best spin a piece can be locked in place with, if it can get there
+pub fn placement_spin(
+    piece: Piece,
+    field: &FieldMatrix,
+    soft_drop: bool,
+    system: RotationSystem,
+    lock_delay: LockDelay,
+    gravity: Gravity,
+) -> Option<Spin> {
+    let target = cells(piece);
+    reachable_placements(
+        piece.piece_type,
+        field,
+        soft_drop,
+        system,
+        lock_delay,
+        gravity,
+    )
+    .into_iter()
+    .find(|(placement, _)| cells(*placement) == target)
+    .map(|(_, spin)| spin)
+}
+
+// whether a T could still be spun into the field for a full T-spin
+pub fn tspin_available(
+    field: &FieldMatrix,
+    system: RotationSystem,
+    lock_delay: LockDelay,
+    gravity: Gravity,
+) -> bool {
+    reachable_placements(PieceType::T, field, true, system, lock_delay, gravity)
+        .iter()
+        .any(|(_, spin)| *spin == Spin::Full)
+}
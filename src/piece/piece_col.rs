@@ -2,7 +2,7 @@ use crate::piece::Rotation;
 use lazy_static::lazy_static;
 use std::ops::{Index, IndexMut};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PieceCollision {
     //clockwise; Normal -> Right -> Double -> Left
     data: [[u8; 16]; 4],
@@ -56,6 +56,24 @@ impl PieceCollision {
         }
     }
 
+    pub fn from_shape(col: [u8; 16], size: usize) -> PieceCollision {
+        PieceCollision {
+            data: PieceCollision::add_rotations(col, size),
+            cur_rotation: Rotation::Normal,
+            size,
+        }
+    }
+
+    // a piece with its rotation states given one by one instead of rotated
+    // from the first, e.g. from a ruleset file
+    pub fn from_states(states: [[u8; 16]; 4], size: usize) -> PieceCollision {
+        PieceCollision {
+            data: states,
+            cur_rotation: Rotation::Normal,
+            size,
+        }
+    }
+
     pub fn set_rotation(&mut self, rot: Rotation) {
         self.cur_rotation = rot;
     }
@@ -75,13 +93,13 @@ impl PieceCollision {
 
     fn add_rotations(col: [u8; 16], size: usize) -> [[u8; 16]; 4] {
         let mut data = [[0; 16]; 4];
-        let col_r = rotate(col.clone(), size, Rotation::Right);
+        let col_r = rotate(col, size, Rotation::Right);
 
         //clockwise; Normal -> Right -> Double -> Left
         data[0] = col;
         data[1] = col_r;
-        data[2] = rotate(col.clone(), size, Rotation::Double);
-        data[3] = rotate(col_r.clone(), size, Rotation::Double);
+        data[2] = rotate(col, size, Rotation::Double);
+        data[3] = rotate(col_r, size, Rotation::Double);
 
         data
     }
@@ -0,0 +1,219 @@
+use super::ruleset::Ruleset;
+use super::{piece_block_positions, Piece, PieceType, Rotation};
+use crate::fieldmatrix::FieldMatrix;
+
+// How pieces turn in different games. Every system's rotation states are
+// the SRS box rotations moved over by a per state offset, so the pieces
+// themselves always keep SRS positions and only the movement engine needs
+// to know which system is used.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RotationSystem<'a> {
+    // guideline SRS
+    Srs,
+    // tetr.io's SRS with symmetric I kicks and 180 rotations
+    SrsPlus,
+    // TGM's Arika rotation system
+    Ars,
+    // classic NES, no kicks at all
+    Nrs,
+    // read from a ruleset file
+    Custom(&'a Ruleset),
+}
+
+// kick tables are written with y going up like the guideline tables are
+const JLSTZ_KICKS: [[(isize, isize); 5]; 8] = [
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)], // 0 -> R
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],     // R -> 0
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],     // R -> 2
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)], // 2 -> R
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],    // 2 -> L
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],  // L -> 2
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],  // L -> 0
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],    // 0 -> L
+];
+
+const I_KICKS: [[(isize, isize); 5]; 8] = [
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)], // 0 -> R
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)], // R -> 0
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)], // R -> 2
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)], // 2 -> R
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)], // 2 -> L
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)], // L -> 2
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)], // L -> 0
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)], // 0 -> L
+];
+
+const I_KICKS_PLUS: [[(isize, isize); 5]; 8] = [
+    [(0, 0), (1, 0), (-2, 0), (-2, -1), (1, 2)], // 0 -> R
+    [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)], // R -> 0
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)], // R -> 2
+    [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)], // 2 -> R
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)], // 2 -> L
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)], // L -> 2
+    [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)], // L -> 0
+    [(0, 0), (-1, 0), (2, 0), (2, -1), (-1, 2)], // 0 -> L
+];
+
+const HALF_TURN_KICKS: [[(isize, isize); 6]; 4] = [
+    [(0, 0), (0, 1), (1, 1), (-1, 1), (1, 0), (-1, 0)], // 0 -> 2
+    [(0, 0), (0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)], // 2 -> 0
+    [(0, 0), (1, 0), (1, 2), (1, 1), (0, 2), (0, 1)],   // R -> L
+    [(0, 0), (-1, 0), (-1, 2), (-1, 1), (0, 2), (0, 1)], // L -> R
+];
+
+const ARS_KICKS: [(isize, isize); 3] = [(0, 0), (1, 0), (-1, 0)];
+
+const NO_KICKS: [(isize, isize); 1] = [(0, 0)];
+
+fn kick_table_row(from: Rotation, to: Rotation) -> usize {
+    match (from, to) {
+        (Rotation::Normal, Rotation::Right) => 0,
+        (Rotation::Right, Rotation::Normal) => 1,
+        (Rotation::Right, Rotation::Double) => 2,
+        (Rotation::Double, Rotation::Right) => 3,
+        (Rotation::Double, Rotation::Left) => 4,
+        (Rotation::Left, Rotation::Double) => 5,
+        (Rotation::Left, Rotation::Normal) => 6,
+        _ => 7,
+    }
+}
+
+fn half_turn_table_row(from: Rotation) -> usize {
+    match from {
+        Rotation::Normal => 0,
+        Rotation::Double => 1,
+        Rotation::Right => 2,
+        Rotation::Left => 3,
+    }
+}
+
+impl<'a> RotationSystem<'a> {
+    pub fn from_name(name: &str) -> Result<RotationSystem<'a>, String> {
+        match name.to_lowercase().as_str() {
+            "srs" => Ok(RotationSystem::Srs),
+            "srs+" | "srsplus" => Ok(RotationSystem::SrsPlus),
+            "ars" => Ok(RotationSystem::Ars),
+            "nrs" => Ok(RotationSystem::Nrs),
+            _ => Err(format!("unknown rotation system: {}", name)),
+        }
+    }
+
+    pub fn has_half_turns(self) -> bool {
+        match self {
+            RotationSystem::SrsPlus => true,
+            RotationSystem::Custom(ruleset) => ruleset.half_turns,
+            _ => false,
+        }
+    }
+
+    // The kicks tried in order, with y going up. Half turns only exist in
+    // systems with has_half_turns.
+    pub fn kicks(
+        self,
+        piece_type: PieceType,
+        from: Rotation,
+        to: Rotation,
+    ) -> &'a [(isize, isize)] {
+        if let RotationSystem::Custom(ruleset) = self {
+            return ruleset.kicks(piece_type, from, to).unwrap_or(&NO_KICKS);
+        }
+        if piece_type == PieceType::O {
+            return &NO_KICKS;
+        }
+        let half_turn = from.clockwise().clockwise() == to;
+
+        match self {
+            RotationSystem::Srs | RotationSystem::SrsPlus if half_turn => {
+                &HALF_TURN_KICKS[half_turn_table_row(from)]
+            }
+            RotationSystem::Srs if piece_type == PieceType::I => &I_KICKS[kick_table_row(from, to)],
+            RotationSystem::SrsPlus if piece_type == PieceType::I => {
+                &I_KICKS_PLUS[kick_table_row(from, to)]
+            }
+            RotationSystem::Srs | RotationSystem::SrsPlus => &JLSTZ_KICKS[kick_table_row(from, to)],
+            RotationSystem::Ars if piece_type == PieceType::I => &NO_KICKS,
+            RotationSystem::Ars => &ARS_KICKS,
+            RotationSystem::Nrs | RotationSystem::Custom(_) => &NO_KICKS,
+        }
+    }
+
+    // Where this system's rotation state sits compared to the SRS box
+    // rotation with the same cells. ARS keeps its pieces against the bottom
+    // of the box, and ARS and NRS only have two states for S, Z and I.
+    pub fn state_offset(self, piece_type: PieceType, rotation: Rotation) -> (isize, isize) {
+        match (self, piece_type, rotation) {
+            (RotationSystem::Custom(ruleset), _, _) => ruleset.state_offset(piece_type, rotation),
+            (RotationSystem::Srs, _, _) | (RotationSystem::SrsPlus, _, _) => (0, 0),
+            (_, PieceType::O, _) => (0, 0),
+            (RotationSystem::Ars, PieceType::I, Rotation::Double) => (0, -1),
+            (RotationSystem::Ars, PieceType::I, Rotation::Left) => (1, 0),
+            (RotationSystem::Ars, PieceType::I, _) => (0, 0),
+            (RotationSystem::Nrs, PieceType::I, Rotation::Normal) => (0, 1),
+            (RotationSystem::Nrs, PieceType::I, Rotation::Left) => (1, 0),
+            (RotationSystem::Nrs, PieceType::I, _) => (0, 0),
+            (RotationSystem::Ars, PieceType::S, Rotation::Right) => (-1, 0),
+            (RotationSystem::Ars, PieceType::Z, Rotation::Left) => (1, 0),
+            (RotationSystem::Nrs, PieceType::T, _)
+            | (RotationSystem::Nrs, PieceType::L, _)
+            | (RotationSystem::Nrs, PieceType::J, _) => (0, 0),
+            (RotationSystem::Nrs, PieceType::S, Rotation::Left)
+            | (RotationSystem::Nrs, PieceType::Z, Rotation::Left) => (1, 0),
+            (_, _, Rotation::Normal) => (0, 1),
+            _ => (0, 0),
+        }
+    }
+
+    // the state and SRS position a piece spawns with, its cells always end
+    // up in the two rows right above the visible field
+    pub fn spawn_piece(self, piece_type: PieceType) -> Piece {
+        if let RotationSystem::Custom(ruleset) = self {
+            return ruleset.spawn_piece(piece_type);
+        }
+        let (rotation, position) = match (self, piece_type) {
+            (_, PieceType::O) => (Rotation::Normal, (4, 2)),
+            (RotationSystem::Srs, _) | (RotationSystem::SrsPlus, _) => (Rotation::Normal, (3, 2)),
+            (RotationSystem::Ars, PieceType::I) => (Rotation::Double, (3, 2)),
+            (_, _) => (Rotation::Double, (3, 1)),
+        };
+        let (dx, dy) = self.state_offset(piece_type, rotation);
+
+        Piece {
+            piece_type,
+            rotation,
+            position: (position.0 + dx, position.1 + dy),
+        }
+    }
+
+    // ARS doesn't kick an L, J or T when the first cell in the way, reading
+    // the box row by row, is in the middle column
+    pub fn kick_allowed(self, rotated: Piece, field: &FieldMatrix) -> bool {
+        if self != RotationSystem::Ars
+            || ![PieceType::L, PieceType::J, PieceType::T].contains(&rotated.piece_type)
+        {
+            return true;
+        }
+
+        let (dx, _) = self.state_offset(rotated.piece_type, rotated.rotation);
+        let box_x = rotated.position.0 - dx;
+        let mut cells = match piece_block_positions(rotated) {
+            Some(cells) => cells,
+            None => return true,
+        };
+        cells.sort_unstable_by_key(|(x, y)| (*y, *x));
+
+        match cells.iter().find(|(x, y)| field[*y][*x] != 0) {
+            Some((x, _)) => *x as isize - box_x != 1,
+            None => true,
+        }
+    }
+
+    // whether a rotation using this kick always counts as a full T-spin,
+    // the last kick of an SRS quarter turn is what TSTs and fins use
+    pub fn upgrades_spin(self, kick: usize, half_turn: bool) -> bool {
+        match self {
+            RotationSystem::Srs | RotationSystem::SrsPlus => !half_turn && kick == 4,
+            RotationSystem::Custom(ruleset) => !half_turn && ruleset.full_spin_kick == Some(kick),
+            _ => false,
+        }
+    }
+}
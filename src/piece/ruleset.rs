@@ -0,0 +1,256 @@
+use super::piece_col::PieceCollision;
+use super::{Piece, PieceType, Rotation, PIECE_TYPES};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// clockwise, in the same order PieceCollision keeps its states
+const ROTATIONS: [Rotation; 4] = [
+    Rotation::Normal,
+    Rotation::Right,
+    Rotation::Double,
+    Rotation::Left,
+];
+
+fn rotation_index(rotation: Rotation) -> usize {
+    match rotation {
+        Rotation::Normal => 0,
+        Rotation::Right => 1,
+        Rotation::Double => 2,
+        Rotation::Left => 3,
+    }
+}
+
+#[derive(Deserialize)]
+struct RulesetFile {
+    #[serde(default)]
+    half_turns: bool,
+    full_spin_kick: Option<usize>,
+    pieces: HashMap<String, PieceFile>,
+}
+
+// States are rows of the piece's box from the top, '.' or '0' for empty
+// cells, listed clockwise starting with the spawn state. A single state gets
+// turned around the middle of its box like the built in shapes are, two
+// states take turns. Kicks are keyed by the two states, e.g. "01"
+// for turning from the spawn state to the next one, with y going up.
+//
+// Piece shapes can't be defined here. Every state has to be one of the SRS
+// shapes, the states only say where it sits in the box.
+#[derive(Deserialize)]
+struct PieceFile {
+    states: Vec<Vec<String>>,
+    spawn: (isize, isize),
+    #[serde(default)]
+    kicks: HashMap<String, Vec<(isize, isize)>>,
+}
+
+// A rotation system read from a file. Only the kicks, spawns and where each
+// state sits in its box can be changed. Custom piece shapes aren't
+// supported: fields are read and pieces collide with the built in SRS
+// shapes, so a state that isn't one of them is an error. The states are
+// stored as offsets from the matching SRS box rotation like the built in
+// systems are.
+#[derive(Debug, PartialEq)]
+pub struct Ruleset {
+    pub half_turns: bool,
+    pub full_spin_kick: Option<usize>,
+    offsets: HashMap<PieceType, [(isize, isize); 4]>,
+    spawns: HashMap<PieceType, (Rotation, (isize, isize))>,
+    kicks: HashMap<(PieceType, usize, usize), Vec<(isize, isize)>>,
+}
+
+impl Ruleset {
+    // .json files are read as JSON, anything else as TOML
+    pub fn load(path: &str) -> Result<Ruleset, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read ruleset {}: {}", path, e))?;
+        if path.ends_with(".json") {
+            Ruleset::from_json(&text)
+        } else {
+            Ruleset::from_toml(&text)
+        }
+    }
+
+    pub fn from_toml(text: &str) -> Result<Ruleset, String> {
+        let file = toml::from_str(text).map_err(|e| format!("bad ruleset: {}", e))?;
+        Ruleset::from_file(file)
+    }
+
+    pub fn from_json(text: &str) -> Result<Ruleset, String> {
+        let file = serde_json::from_str(text).map_err(|e| format!("bad ruleset: {}", e))?;
+        Ruleset::from_file(file)
+    }
+
+    fn from_file(file: RulesetFile) -> Result<Ruleset, String> {
+        let mut ruleset = Ruleset {
+            half_turns: file.half_turns,
+            full_spin_kick: file.full_spin_kick,
+            offsets: HashMap::new(),
+            spawns: HashMap::new(),
+            kicks: HashMap::new(),
+        };
+
+        for piece_type in PIECE_TYPES.iter() {
+            let name = piece_type.to_string();
+            let piece_file = file
+                .pieces
+                .get(&name)
+                .ok_or(format!("the ruleset doesn't define the {} piece", name))?;
+
+            let shape = parse_states(&name, &piece_file.states)?;
+            let srs = Piece {
+                piece_type: *piece_type,
+                rotation: Rotation::Normal,
+                position: (0, 0),
+            }
+            .collision();
+
+            // the SRS rotation the spawn state matches, the rest follow it
+            let base = (0..4)
+                .find(|r| offset_between(&state_cells(&shape, 0), &state_cells(&srs, *r)).is_some())
+                .ok_or(format!(
+                    "the {} piece's spawn state isn't the SRS {} shape, rulesets can't \
+                     change piece shapes",
+                    name, name
+                ))?;
+
+            let mut offsets = [(0, 0); 4];
+            for (k, offset) in (0..4).map(|k| (k, (base + k) % 4)) {
+                offsets[offset] =
+                    offset_between(&state_cells(&shape, k), &state_cells(&srs, offset)).ok_or(
+                        format!(
+                            "state {} of the {} piece isn't the next one turned clockwise",
+                            k, name
+                        ),
+                    )?;
+            }
+
+            let (x, y) = piece_file.spawn;
+            let (dx, dy) = offsets[base];
+            ruleset
+                .spawns
+                .insert(*piece_type, (ROTATIONS[base], (x + dx, y + dy)));
+
+            for (key, kicks) in piece_file.kicks.iter() {
+                let states = key
+                    .chars()
+                    .map(|c| c.to_digit(10).filter(|n| *n < 4).map(|n| n as usize))
+                    .collect::<Option<Vec<usize>>>();
+                let (from, to) = match states.as_deref() {
+                    Some([from, to]) if from != to => (*from, *to),
+                    _ => {
+                        return Err(format!(
+                            "bad kick table name for the {} piece: {}",
+                            name, key
+                        ))
+                    }
+                };
+                ruleset.kicks.insert(
+                    (*piece_type, (base + from) % 4, (base + to) % 4),
+                    kicks.clone(),
+                );
+            }
+
+            ruleset.offsets.insert(*piece_type, offsets);
+        }
+
+        Ok(ruleset)
+    }
+
+    pub fn state_offset(&self, piece_type: PieceType, rotation: Rotation) -> (isize, isize) {
+        self.offsets[&piece_type][rotation_index(rotation)]
+    }
+
+    pub fn spawn_piece(&self, piece_type: PieceType) -> Piece {
+        let (rotation, position) = self.spawns[&piece_type];
+        Piece {
+            piece_type,
+            rotation,
+            position,
+        }
+    }
+
+    pub fn kicks(
+        &self,
+        piece_type: PieceType,
+        from: Rotation,
+        to: Rotation,
+    ) -> Option<&[(isize, isize)]> {
+        self.kicks
+            .get(&(piece_type, rotation_index(from), rotation_index(to)))
+            .map(|kicks| &kicks[..])
+    }
+}
+
+fn parse_states(name: &str, states: &[Vec<String>]) -> Result<PieceCollision, String> {
+    let size = states.first().map(|rows| rows.len()).unwrap_or(0);
+    if ![1, 2, 4].contains(&states.len()) || !(2..=4).contains(&size) {
+        return Err(format!(
+            "the {} piece needs 1, 2 or 4 states of 2 to 4 rows each",
+            name
+        ));
+    }
+
+    let mut parsed = Vec::new();
+    for rows in states.iter() {
+        let mut state = [0; 16];
+        if rows.len() != size || rows.iter().any(|row| row.chars().count() != size) {
+            return Err(format!(
+                "the {} piece's states aren't all the same square size",
+                name
+            ));
+        }
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                state[y * size + x] = if c == '.' || c == '0' { 0 } else { 1 };
+            }
+        }
+        if state.iter().filter(|n| **n == 1).count() != 4 {
+            return Err(format!("the {} piece's states need 4 blocks each", name));
+        }
+        parsed.push(state);
+    }
+
+    if parsed.len() == 1 {
+        return Ok(PieceCollision::from_shape(parsed[0], size));
+    }
+    let mut data = [[0; 16]; 4];
+    for (i, state) in data.iter_mut().enumerate() {
+        *state = parsed[i % parsed.len()];
+    }
+    Ok(PieceCollision::from_states(data, size))
+}
+
+fn state_cells(shape: &PieceCollision, rotation: usize) -> Vec<(isize, isize)> {
+    let mut shape = shape.clone();
+    shape.set_rotation(ROTATIONS[rotation]);
+    let size = shape.size;
+
+    let mut cells = shape
+        .flat_iter()
+        .enumerate()
+        .filter(|(_, n)| **n == 1)
+        .map(|(i, _)| ((i % size) as isize, (i / size) as isize))
+        .collect::<Vec<(isize, isize)>>();
+    cells.sort_unstable();
+    cells
+}
+
+// how far the first set of cells is moved over from the second, if they
+// have the same shape
+fn offset_between(
+    cells: &[(isize, isize)],
+    reference: &[(isize, isize)],
+) -> Option<(isize, isize)> {
+    let (dx, dy) = (cells[0].0 - reference[0].0, cells[0].1 - reference[0].1);
+    if cells.len() == reference.len()
+        && cells
+            .iter()
+            .zip(reference.iter())
+            .all(|((x, y), (rx, ry))| x - rx == dx && y - ry == dy)
+    {
+        Some((dx, dy))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,108 @@
+use super::lock_delay::LockDelay;
+use super::rotation_system::RotationSystem;
+use super::{piece_block_positions, piece_fits_over, Piece, PieceType, Rotation};
+use crate::fieldmatrix::FieldMatrix;
+use std::collections::HashSet;
+
+// The field is 24 rows, the top 4 are the buffer zone above the 20 visible
+// rows. Pieces spawn in the two buffer rows right above the visible rows.
+pub const BUFFER_ROWS: usize = 4;
+
+// The piece as it spawns, turned to this rotation before it appears (IRS)
+// without any kicks, so it stays in the system's spawn box.
+fn spawn_turned(piece_type: PieceType, rotation: Rotation, system: RotationSystem) -> Piece {
+    let spawn = system.spawn_piece(piece_type);
+    let (from_x, from_y) = system.state_offset(piece_type, spawn.rotation);
+    let (to_x, to_y) = system.state_offset(piece_type, rotation);
+    Piece { rotation, ..spawn }.clone_with_offset(to_x - from_x, to_y - from_y)
+}
+
+fn fits(piece: Piece, field: &FieldMatrix) -> bool {
+    piece_fits_over(piece, 0, field).unwrap_or_default()
+}
+
+// the game is over if a piece can't spawn
+pub fn blocked_out(piece_type: PieceType, field: &FieldMatrix, system: RotationSystem) -> bool {
+    !fits(system.spawn_piece(piece_type), field)
+}
+
+// the game is also over if a piece locks completely inside the buffer zone
+pub fn locked_out(piece: Piece) -> bool {
+    match piece_block_positions(piece) {
+        Some(positions) => positions.iter().all(|(_, y)| *y < BUFFER_ROWS),
+        None => false,
+    }
+}
+
+// The piece gets rotated at spawn, slid over at spawn height and dropped
+// straight down, landing in place.
+pub fn harddrop_from_spawn(piece: Piece, field: &FieldMatrix, system: RotationSystem) -> bool {
+    if blocked_out(piece.piece_type, field, system) {
+        return false;
+    }
+
+    let mut current = spawn_turned(piece.piece_type, piece.rotation, system);
+    if !fits(current, field) || piece.position.1 < current.position.1 {
+        return false;
+    }
+
+    let dx = (piece.position.0 - current.position.0).signum();
+    while current.position.0 != piece.position.0 {
+        current = current.clone_with_offset(dx, 0);
+        if !fits(current, field) {
+            return false;
+        }
+    }
+    while current.position.1 != piece.position.1 {
+        current = current.clone_with_offset(0, 1);
+        if !fits(current, field) {
+            return false;
+        }
+    }
+
+    !fits(current.clone_with_offset(0, 1), field)
+}
+
+// Like harddrop_from_spawn, but the piece can be moved left and right
+// anywhere on the way down, as long as the lock delay lets it slide along
+// the ground.
+pub fn softdrop_from_spawn(
+    piece: Piece,
+    field: &FieldMatrix,
+    system: RotationSystem,
+    lock_delay: LockDelay,
+) -> bool {
+    if blocked_out(piece.piece_type, field, system) {
+        return false;
+    }
+
+    let start = spawn_turned(piece.piece_type, piece.rotation, system);
+    if !fits(start, field) {
+        return false;
+    }
+
+    let start_state = lock_delay.start(start);
+    let mut visited = HashSet::new();
+    visited.insert((start.position, start_state));
+    let mut stack = vec![(start, start_state)];
+
+    while let Some((current, state)) = stack.pop() {
+        if current.position == piece.position {
+            return true;
+        }
+
+        for (dx, dy) in [(-1, 0), (1, 0), (0, 1)].iter() {
+            let next = current.clone_with_offset(*dx, *dy);
+            if !fits(next, field) {
+                continue;
+            }
+            if let Some(next_state) = lock_delay.after_move(state, current, next, field) {
+                if visited.insert((next.position, next_state)) {
+                    stack.push((next, next_state));
+                }
+            }
+        }
+    }
+
+    false
+}
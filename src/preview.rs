@@ -0,0 +1,275 @@
+use crate::field::{unknown_pieces, PercentageOptions};
+use crate::fieldmatrix::FieldMatrix;
+use crate::hold_comp::FinalHold;
+use crate::piece::dependency::PlacementGraph;
+use crate::piece::movement::cells;
+use crate::piece::{
+    piece_can_be_placed, piece_in_region, place_piece_on_field, placements_in_region, Piece,
+    PieceType,
+};
+use std::collections::HashMap;
+
+// What the player knows partway through the queue. The placed spots are
+// kept per setup, None once a placement doesn't fit the setup anymore.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct State {
+    field: FieldMatrix,
+    placed: Vec<Option<Vec<bool>>>,
+    hold: Option<PieceType>,
+    // how many times hold has been used, and whether a piece has been placed
+    holds: usize,
+    started: bool,
+    // the current piece followed by the previews
+    visible: Vec<PieceType>,
+    unseen: Vec<PieceType>,
+}
+
+struct Search<'a> {
+    graphs: Vec<PlacementGraph>,
+    dont_care: &'a FieldMatrix,
+    options: PercentageOptions<'a>,
+    hold: bool,
+    previews: usize,
+    memo: HashMap<State, f64>,
+}
+
+// Like find_percentage, but the player only sees the current piece and the
+// next few pieces when placing it. Every placement is picked to give the
+// best chance of finishing a setup, averaged over the pieces that could
+// show up next. With enough previews to see the whole queue this is the
+// same as find_percentage. Every order of the pieces is taken to be as
+// likely as the others, so options.randomizer isn't used.
+pub fn find_preview_percentage(
+    base_field: FieldMatrix,
+    setups: Vec<Vec<Piece>>,
+    dont_care: &FieldMatrix,
+    options: PercentageOptions,
+    previews: usize,
+) -> f64 {
+    let mut unseen = match unknown_pieces(&setups[0], options) {
+        Ok(unseen) => unseen,
+        Err(_) => return 0.0,
+    };
+    unseen.sort_unstable();
+
+    let graphs = setups
+        .iter()
+        .map(|setup| PlacementGraph::new(setup, &base_field, dont_care, options))
+        .collect::<Vec<PlacementGraph>>();
+    let placed = graphs
+        .iter()
+        .map(|graph| Some(vec![false; graph.pieces.len()]))
+        .collect();
+
+    let mut search = Search {
+        graphs,
+        dont_care,
+        options,
+        hold: options.hold && options.gravity.allows_hold(),
+        previews,
+        memo: HashMap::new(),
+    };
+    let state = State {
+        field: base_field,
+        placed,
+        hold: options.initial_hold,
+        holds: 0,
+        started: false,
+        visible: options.known_queue.to_vec(),
+        unseen,
+    };
+
+    search.reveal(state) * 100.0
+}
+
+impl<'a> Search<'a> {
+    // shows pieces until the previews are full, every distinct queue being
+    // as likely as the others
+    fn reveal(&mut self, state: State) -> f64 {
+        if state.visible.len() > self.previews || state.unseen.is_empty() {
+            return self.decide(state);
+        }
+
+        let total = state.unseen.len() as f64;
+        let mut types = state.unseen.clone();
+        types.dedup();
+
+        let mut chance = 0.0;
+        for piece_type in types.into_iter() {
+            let count = state.unseen.iter().filter(|t| **t == piece_type).count();
+            let mut next = state.clone();
+            let i = next.unseen.iter().position(|t| *t == piece_type).unwrap();
+            next.unseen.remove(i);
+            next.visible.push(piece_type);
+            chance += count as f64 / total * self.reveal(next);
+        }
+        chance
+    }
+
+    // the best chance of finishing a setup from here
+    fn decide(&mut self, state: State) -> f64 {
+        if let Some(chance) = self.memo.get(&state) {
+            return *chance;
+        }
+
+        let rules = self.options.hold_rules;
+        let chance = match state.visible.split_first() {
+            None => {
+                let done = state.placed.iter().flatten().any(|p| p.iter().all(|p| *p));
+                match state.hold {
+                    _ if done && rules.ends_with(state.hold) => 1.0,
+                    // the held piece is the last one left, swapped out for
+                    // the next piece
+                    Some(held)
+                        if rules.final_hold == FinalHold::Any && rules.can_use(state.holds) =>
+                    {
+                        self.best_placement(&state, held, None, state.holds + 1, 0)
+                    }
+                    _ => 0.0,
+                }
+            }
+            Some((current, _)) => {
+                let mut chance = self.best_placement(&state, *current, state.hold, state.holds, 1);
+                let can_hold = rules.can_use(state.holds) && (state.started || rules.first_piece);
+                if self.hold && can_hold {
+                    let held = match state.hold {
+                        // swap the held piece in and place it
+                        Some(held) => {
+                            self.best_placement(&state, held, Some(*current), state.holds + 1, 1)
+                        }
+                        // hold the current piece and move on to the next one
+                        None => {
+                            let mut next = state.clone();
+                            next.hold = Some(*current);
+                            next.holds += 1;
+                            next.visible.remove(0);
+                            self.reveal(next)
+                        }
+                    };
+                    chance = chance.max(held);
+                }
+                chance
+            }
+        };
+
+        self.memo.insert(state, chance);
+        chance
+    }
+
+    // places the piece where it does the most good, leaving hold with the
+    // given piece after being used holds times and taking used pieces off
+    // the front of the queue
+    fn best_placement(
+        &mut self,
+        state: &State,
+        piece_type: PieceType,
+        hold: Option<PieceType>,
+        holds: usize,
+        used: usize,
+    ) -> f64 {
+        let mut best: f64 = 0.0;
+        for (piece, placed) in self.placements(state, piece_type).into_iter() {
+            let mut next = state.clone();
+            place_piece_on_field(piece, &mut next.field);
+            next.placed = placed;
+            next.hold = hold;
+            next.holds = holds;
+            next.started = true;
+            next.visible.drain(..used);
+            best = best.max(self.reveal(next));
+            if best == 1.0 {
+                break;
+            }
+        }
+        best
+    }
+
+    // every spot the piece can go in for any setup still being built, with
+    // the setups it keeps alive
+    fn placements(
+        &self,
+        state: &State,
+        piece_type: PieceType,
+    ) -> Vec<(Piece, Vec<Option<Vec<bool>>>)> {
+        let mut candidates: Vec<Piece> = Vec::new();
+        for (graph, placed) in self.graphs.iter().zip(state.placed.iter()) {
+            let placed = match placed {
+                Some(placed) => placed,
+                None => continue,
+            };
+            let mut tried_dont_care = false;
+            for i in self.open_slots(graph, placed, piece_type) {
+                let piece = graph.pieces[i];
+                if piece_in_region(piece, self.dont_care) {
+                    if !tried_dont_care {
+                        tried_dont_care = true;
+                        candidates.extend(placements_in_region(
+                            piece_type,
+                            &state.field,
+                            self.dont_care,
+                            self.options,
+                        ));
+                    }
+                } else if piece_can_be_placed(piece, &state.field, self.options) {
+                    candidates.push(piece);
+                }
+            }
+        }
+
+        let mut seen = Vec::new();
+        candidates.retain(|piece| {
+            let piece_cells = cells(*piece);
+            if seen.contains(&piece_cells) {
+                return false;
+            }
+            seen.push(piece_cells);
+            true
+        });
+
+        candidates
+            .into_iter()
+            .map(|piece| (piece, self.place(state, piece)))
+            .collect()
+    }
+
+    // the slots of this type that are empty and have everything they wait
+    // on placed
+    fn open_slots(
+        &self,
+        graph: &PlacementGraph,
+        placed: &[bool],
+        piece_type: PieceType,
+    ) -> Vec<usize> {
+        (0..graph.pieces.len())
+            .filter(|i| !placed[*i] && graph.pieces[*i].piece_type == piece_type)
+            .filter(|i| graph.predecessors(*i).iter().all(|j| placed[*j]))
+            .collect()
+    }
+
+    fn place(&self, state: &State, piece: Piece) -> Vec<Option<Vec<bool>>> {
+        let piece_cells = cells(piece);
+        let in_dont_care = piece_in_region(piece, self.dont_care);
+
+        self.graphs
+            .iter()
+            .zip(state.placed.iter())
+            .map(|(graph, placed)| {
+                let placed = placed.as_ref()?;
+                let slot = self
+                    .open_slots(graph, placed, piece.piece_type)
+                    .into_iter()
+                    .find(|i| {
+                        let slot = graph.pieces[*i];
+                        if piece_in_region(slot, self.dont_care) {
+                            in_dont_care
+                        } else {
+                            cells(slot) == piece_cells
+                        }
+                    })?;
+                let mut placed = placed.clone();
+                placed[slot] = true;
+                Some(placed)
+            })
+            .collect()
+    }
+}
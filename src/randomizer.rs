@@ -0,0 +1,118 @@
+use crate::bag::Bag;
+use crate::piece::{PieceType, PIECE_TYPES};
+
+// Where queues come from. Games deal pieces very differently, so queues
+// get weighted by how likely the game's randomizer is to deal them. Queues
+// get checked on several threads, so randomizers have to be shareable.
+pub trait Randomizer: Sync {
+    // the chance of these being the next pieces dealt
+    fn queue_chance(&self, queue: &[PieceType]) -> f64;
+
+    // how many of each piece a bag holds, for randomizers that deal bags
+    fn bag_repeats(&self) -> Option<u16> {
+        None
+    }
+}
+
+pub fn from_name(name: &str) -> Result<Box<dyn Randomizer>, String> {
+    match name.to_lowercase().as_str() {
+        "7bag" | "bag" => Ok(Box::new(Bag::new(1))),
+        "14bag" => Ok(Box::new(Bag::new(2))),
+        "random" | "memoryless" => Ok(Box::new(Memoryless)),
+        "tgm" | "tgm1" => Ok(Box::new(History::tgm1())),
+        "tgm3" => Ok(Box::new(History::tgm3())),
+        "nes" => Ok(Box::new(Nes { previous: None })),
+        _ => Err(format!("unknown randomizer: {}", name)),
+    }
+}
+
+// every piece equally likely every time
+pub struct Memoryless;
+
+impl Randomizer for Memoryless {
+    fn queue_chance(&self, queue: &[PieceType]) -> f64 {
+        (1.0 / PIECE_TYPES.len() as f64).powi(queue.len() as i32)
+    }
+}
+
+// TGM rolls up to a few times for a piece that isn't one of the last four
+// dealt, keeping the last roll if they all were. The first piece is never
+// an S, Z or O.
+pub struct History {
+    history: Vec<PieceType>,
+    rolls: i32,
+}
+
+impl History {
+    pub fn tgm1() -> History {
+        History {
+            history: vec![PieceType::Z; 4],
+            rolls: 4,
+        }
+    }
+
+    // TGM3 also swaps pieces that haven't come in a while into its rolls,
+    // which isn't modelled, only its longer history
+    pub fn tgm3() -> History {
+        History {
+            history: vec![PieceType::S, PieceType::Z, PieceType::S, PieceType::Z],
+            rolls: 6,
+        }
+    }
+}
+
+impl Randomizer for History {
+    fn queue_chance(&self, queue: &[PieceType]) -> f64 {
+        let first_pieces = [PieceType::I, PieceType::J, PieceType::L, PieceType::T];
+        let roll = 1.0 / PIECE_TYPES.len() as f64;
+        let mut history = self.history.clone();
+        let mut chance = 1.0;
+
+        for (i, piece_type) in queue.iter().enumerate() {
+            chance *= if i == 0 {
+                if first_pieces.contains(piece_type) {
+                    1.0 / first_pieces.len() as f64
+                } else {
+                    0.0
+                }
+            } else {
+                // how likely a roll is to hit the history
+                let in_history =
+                    PIECE_TYPES.iter().filter(|p| history.contains(p)).count() as f64 * roll;
+                if history.contains(piece_type) {
+                    roll * in_history.powi(self.rolls - 1)
+                } else {
+                    roll * (0..self.rolls).map(|k| in_history.powi(k)).sum::<f64>()
+                }
+            };
+            history.remove(0);
+            history.push(*piece_type);
+        }
+        chance
+    }
+}
+
+// The NES rolls 8 values, one of them not a piece. That or the piece just
+// dealt gets rerolled once, and the reroll is kept whatever it is.
+pub struct Nes {
+    previous: Option<PieceType>,
+}
+
+impl Randomizer for Nes {
+    fn queue_chance(&self, queue: &[PieceType]) -> f64 {
+        let mut previous = self.previous;
+        let mut chance = 1.0;
+
+        for piece_type in queue.iter() {
+            let reroll = if previous.is_some() { 2.0 } else { 1.0 } / 8.0;
+            let first_roll = if previous == Some(*piece_type) {
+                0.0
+            } else {
+                1.0 / 8.0
+            };
+            chance *= first_roll + reroll / 7.0;
+            previous = Some(*piece_type);
+        }
+        chance
+    }
+}
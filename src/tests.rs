@@ -8,13 +8,13 @@ fn test_piece_block_positions() {
         rotation: Rotation::Normal,
     };
 
-    assert!(piece_block_positions(piece) != None);
+    assert!(piece_block_positions(piece).is_some());
 
     piece.position = (0, 0);
-    assert!(piece_block_positions(piece) != None);
+    assert!(piece_block_positions(piece).is_some());
 
     piece.position = (9, 0);
-    assert!(piece_block_positions(piece) == None);
+    assert!(piece_block_positions(piece).is_none());
 
     piece.position = (7, 16);
     piece.rotation = Rotation::Right;
@@ -23,6 +23,8 @@ fn test_piece_block_positions() {
 
 #[test]
 fn test_can_harddrop() {
+    use crate::piece::rotation_system::RotationSystem;
+
     let mut piece = Piece {
         piece_type: PieceType::I,
         position: (1, 1),
@@ -31,13 +33,13 @@ fn test_can_harddrop() {
 
     let field = [[0; 10]; 24];
 
-    assert!(can_harddrop(piece, &field) == false);
+    assert!(!can_harddrop(piece, &field, RotationSystem::Srs));
 
     piece.position = (0, 22);
-    assert!(can_harddrop(piece, &field) == true);
+    assert!(can_harddrop(piece, &field, RotationSystem::Srs));
 
     piece.position = (6, 22);
-    assert!(can_harddrop(piece, &field) == true);
+    assert!(can_harddrop(piece, &field, RotationSystem::Srs));
 }
 
 #[test]
@@ -48,14 +50,13 @@ fn test_unused_points() {
         rotation: Rotation::Normal,
     };
 
-    let mut pieces = Vec::new();
-    pieces.push(piece);
+    let pieces = vec![piece];
 
     let mut field = [[0; 10]; 24];
-    assert!(find_unused_points(&pieces, &field).len() == 0);
+    assert!(find_unused_points(&pieces, &field).is_empty());
 
     field[1][5] = 1;
-    assert!(find_unused_points(&pieces, &field).len() == 0);
+    assert!(find_unused_points(&pieces, &field).is_empty());
 
     field[0][0] = 2;
     let points = find_unused_points(&pieces, &field);
@@ -68,5 +69,1396 @@ fn test_unused_points() {
     field[3][2] = 3;
     field[4][2] = 3;
 
-    assert!(find_unused_points(&pieces, &field).len() == 0);
+    // only the block shared with the I piece is used
+    let points = find_unused_points(&pieces, &field);
+    assert!(points == vec![(2, 1), (2, 3), (2, 4)]);
+}
+
+// builds a fumen style field from rows of piece letters, 'X' for grey and '.'
+// for empty. The last row given ends up just above the garbage row.
+fn field_from_rows(rows: &[&str]) -> crate::fieldmatrix::FieldMatrix {
+    let mut field = [[0; 10]; 24];
+    let top = 23 - rows.len();
+
+    for (y, row) in rows.iter().enumerate() {
+        for (x, c) in row.chars().enumerate() {
+            field[top + y][x] = match c {
+                '.' => 0,
+                'X' => 8,
+                c => piece_type_to_fumen_index(char_to_piece_type(c).unwrap()),
+            };
+        }
+    }
+
+    field
+}
+
+// A J, I and Z on an empty field. The I has to go in first, or second with
+// hold.
+fn jiz_setup() -> (crate::fieldmatrix::FieldMatrix, Vec<Piece>) {
+    use crate::field::{apply_garbage, split_color};
+
+    let field = apply_garbage(
+        field_from_rows(&["JJ........", "J...ZZ....", "JIIIIZZ..."]),
+        0,
+    );
+    let (base_field, color_field) = split_color(field);
+    (base_field, color_field_to_pieces(color_field).unwrap())
+}
+
+#[test]
+fn test_mirror_pieces() {
+    let field = field_from_rows(&["JJ........", "J...ZZ....", "JIIIIZZ..."]);
+    let (_, color_field) = crate::field::split_color(field);
+    let (_, mirrored_color_field) = crate::field::split_color(crate::field::mirror(field));
+
+    let pieces = color_field_to_pieces(color_field).unwrap();
+    let mirrored_pieces = color_field_to_pieces(mirrored_color_field).unwrap();
+    assert!(pieces.len() == mirrored_pieces.len());
+
+    for piece in pieces.iter() {
+        let mirrored = piece.mirror();
+        let mut positions = piece_block_positions(mirrored).unwrap();
+        positions.sort();
+
+        assert!(mirrored_pieces.iter().any(|other| {
+            let mut other_positions = piece_block_positions(*other).unwrap();
+            other_positions.sort();
+            other.piece_type == mirrored.piece_type && other_positions == positions
+        }));
+    }
+
+    assert!(mirror_queue("SZLJTOI").unwrap() == "ZSJLTOI");
+    assert!(mirror_queue("SZX").is_err());
+}
+
+#[test]
+fn test_mirror_percentage() {
+    use crate::field::{apply_garbage, find_percentage, mirror, split_color, PercentageOptions};
+
+    let no_dont_care = [[0; 10]; 24];
+    let fields = [
+        apply_garbage(
+            field_from_rows(&["JJ........", "J...ZZ....", "JIIIIZZ..."]),
+            0,
+        ),
+        apply_garbage(
+            field_from_rows(&["L......SS.", "L.OO..SSX.", "LLOOIIIIX."]),
+            0,
+        ),
+        // the O has to be softdropped and slid under the overhang
+        apply_garbage(
+            field_from_rows(&["XXXX......", "..OO......", "..OO......", "XXXXXXXXX."]),
+            0,
+        ),
+    ];
+
+    for field in fields.iter() {
+        let (base_field, color_field) = split_color(*field);
+        let (mirrored_base_field, mirrored_color_field) = split_color(mirror(*field));
+        let pieces = color_field_to_pieces(color_field).unwrap();
+        let mirrored_pieces = color_field_to_pieces(mirrored_color_field).unwrap();
+
+        for hold in [false, true].iter() {
+            for soft_drop in [false, true].iter() {
+                let options = PercentageOptions::new(*hold, *soft_drop, false);
+                let percent =
+                    find_percentage(base_field, vec![pieces.clone()], &no_dont_care, options);
+                let mirrored_percent = find_percentage(
+                    mirrored_base_field,
+                    vec![mirrored_pieces.clone()],
+                    &no_dont_care,
+                    options,
+                );
+
+                assert!(percent == mirrored_percent);
+            }
+        }
+    }
+
+    let (base_field, color_field) = split_color(fields[0]);
+    let pieces = color_field_to_pieces(color_field).unwrap();
+    let no_hold = PercentageOptions::new(false, false, false);
+    let hold = PercentageOptions::new(true, false, false);
+    assert!(
+        (find_percentage(base_field, vec![pieces.clone()], &no_dont_care, no_hold) - 100.0 / 3.0)
+            .abs()
+            < 1e-9
+    );
+    assert!(
+        (find_percentage(base_field, vec![pieces], &no_dont_care, hold) - 200.0 / 3.0).abs() < 1e-9
+    );
+
+    let (base_field, color_field) = split_color(fields[2]);
+    let pieces = color_field_to_pieces(color_field).unwrap();
+    let harddrop = PercentageOptions::new(false, false, false);
+    let softdrop = PercentageOptions::new(false, true, false);
+    assert!(find_percentage(base_field, vec![pieces.clone()], &no_dont_care, harddrop) == 0.0);
+    assert!(find_percentage(base_field, vec![pieces], &no_dont_care, softdrop) == 100.0);
+}
+
+#[test]
+fn test_garbage_rows() {
+    use crate::field::{apply_garbage, find_percentage, split_color, PercentageOptions, FLOOR_ROW};
+
+    let mut field = field_from_rows(&["......OO..", "......OO..", "IIIII.IIII"]);
+    field[23] = [8, 8, 8, 8, 8, 0, 8, 8, 8, 8];
+
+    // without garbage the bottom row of the field rests on the floor
+    let discarded = apply_garbage(field, 0);
+    assert!(discarded[FLOOR_ROW] == field[22]);
+    assert!(discarded[0] == [0; 10]);
+
+    // kept garbage turns grey but keeps its holes
+    let kept = apply_garbage(field, 2);
+    assert!(kept[FLOOR_ROW] == field[23]);
+    assert!(kept[22] == [8, 8, 8, 8, 8, 0, 8, 8, 8, 8]);
+
+    let (base_field, color_field) = split_color(kept);
+    let pieces = color_field_to_pieces(color_field).unwrap();
+    assert!(pieces.len() == 1);
+    assert!(impossibilites(&pieces, &kept).is_empty());
+
+    let options = PercentageOptions::new(false, false, false);
+    assert!(find_percentage(base_field, vec![pieces], &[[0; 10]; 24], options) == 100.0);
+
+    // an I sitting over the holes has nothing under it
+    let mut field = field_from_rows(&["IIII......"]);
+    field[23] = [0, 0, 0, 0, 8, 8, 8, 8, 8, 8];
+    let kept = apply_garbage(field, 1);
+    let (_, color_field) = split_color(kept);
+    let pieces = color_field_to_pieces(color_field).unwrap();
+    assert!(impossibilites(&pieces, &kept).len() == 1);
+}
+
+#[test]
+fn test_dont_care() {
+    use crate::field::PercentageOptions;
+    use crate::field::{apply_garbage, dont_care_mask, find_percentage, split_color};
+
+    let options = PercentageOptions::new(false, false, false);
+    let field = apply_garbage(
+        field_from_rows(&["JJ.OO.....", "J..OO.....", "JIIII....."]),
+        0,
+    );
+    let (base_field, color_field) = split_color(field);
+    let pieces = color_field_to_pieces(color_field).unwrap();
+
+    // the O no longer has to wait for the I when it can go on the floor to
+    // the right, leaving only the J needing to go after the I
+    let dont_care = dont_care_mask(apply_garbage(
+        field_from_rows(&["...XXXXXXX", "...XXXXXXX", ".....XXXXX"]),
+        0,
+    ));
+    assert!(piece_in_region(pieces[0], &dont_care) != piece_in_region(pieces[1], &dont_care));
+    let percent = find_percentage(base_field, vec![pieces.clone()], &[[0; 10]; 24], options);
+    assert!((percent - 100.0 / 3.0).abs() < 1e-9);
+    assert!(find_percentage(base_field, vec![pieces.clone()], &dont_care, options) == 50.0);
+
+    // there is nowhere for a floating O to go
+    let dont_care = dont_care_mask(apply_garbage(
+        field_from_rows(&["........XX", "........XX", ".........."]),
+        0,
+    ));
+    let floating = field_from_rows(&["........OO", "........OO", ".........."]);
+    let (_, color_field) = split_color(apply_garbage(floating, 0));
+    let pieces = color_field_to_pieces(color_field).unwrap();
+    assert!(find_percentage(base_field, vec![pieces], &dont_care, options) == 0.0);
+}
+
+#[test]
+fn test_tiling() {
+    use crate::field::{apply_garbage, group_setups, split_color, split_grey_region};
+    use crate::tiling::{region_candidates, tile_region};
+
+    // two O pieces next to each other can only be split one way
+    let field = apply_garbage(field_from_rows(&["OOOO......", "OOOO......"]), 0);
+    let (_, color_field) = split_color(field);
+    assert!(color_field_to_pieces(color_field).is_err());
+    let setups = color_field_to_setups(color_field).unwrap();
+    assert!(setups.len() == 1);
+    let mut positions = setups[0]
+        .iter()
+        .map(|piece| piece.position)
+        .collect::<Vec<_>>();
+    positions.sort();
+    assert!(positions == vec![(0, 22), (2, 22)]);
+
+    // a 4x2 rectangle fits two of O, I, L or J
+    let field = apply_garbage(field_from_rows(&["XXXX......", "XXXX......"]), 0);
+    let (base_field, _) = split_color(field);
+    let (garbage, region) = split_grey_region(base_field, 0);
+    assert!(garbage == [[0; 10]; 24]);
+
+    let candidates = region_candidates(&region);
+    let tilings = tile_region(&region, &candidates, None);
+    assert!(tilings.len() == 4);
+    assert!(group_setups(tilings.clone()).len() == 4);
+    for tiling in tilings.iter() {
+        assert!(tiling.len() == 2);
+        // the pieces cover every cell of the region, and nothing else
+        let mut cells = tiling
+            .iter()
+            .flat_map(|piece| piece_block_positions(*piece).unwrap())
+            .collect::<Vec<(usize, usize)>>();
+        cells.sort_unstable();
+        let mut region_cells = (0..24)
+            .flat_map(|y| (0..10).map(move |x| (x, y)))
+            .filter(|(x, y)| region[*y][*x] == 1)
+            .collect::<Vec<(usize, usize)>>();
+        region_cells.sort_unstable();
+        assert!(cells == region_cells);
+    }
+
+    let i_pieces = [PieceType::I, PieceType::I];
+    let tilings = tile_region(&region, &candidates, Some(&i_pieces));
+    assert!(tilings.len() == 1);
+    assert!(tilings[0]
+        .iter()
+        .all(|piece| piece.piece_type == PieceType::I));
+
+    let t_pieces = [PieceType::T, PieceType::T];
+    assert!(tile_region(&region, &candidates, Some(&t_pieces)).is_empty());
+    assert!(tile_region(&region, &candidates, Some(&t_pieces[..1])).is_empty());
+
+    // garbage rows stay garbage
+    let (garbage, region) = split_grey_region(base_field, 1);
+    assert!(garbage[23][0] == 8 && garbage[22][0] == 0);
+    assert!(region[23][0] == 0 && region[22][0] == 1);
+}
+
+#[test]
+fn test_same_type_pieces() {
+    use crate::field::{apply_garbage, find_percentage, split_color, PercentageOptions};
+    use crate::perm_gen::generate_perm_iter;
+
+    assert!(generate_perm_iter(vec![PieceType::L, PieceType::L, PieceType::T]).count() == 3);
+    assert!(generate_perm_iter(vec![1, 2, 3, 4]).count() == 24);
+    assert!(generate_perm_iter(Vec::<u8>::new()).count() == 1);
+
+    let no_hold = PercentageOptions::new(false, false, false);
+    let dont_care = [[0; 10]; 24];
+
+    // either I can go on the bottom, so II always works
+    let field = apply_garbage(field_from_rows(&["IIII......", "IIII......"]), 0);
+    let (base_field, color_field) = split_color(field);
+    let pieces = color_field_to_pieces(color_field).unwrap();
+    assert!(find_percentage(base_field, vec![pieces], &dont_care, no_hold) == 100.0);
+
+    // only OII works without hold, IOI works with hold too
+    let field = apply_garbage(
+        field_from_rows(&["IIII......", "IIII......", "OO........", "OO........"]),
+        0,
+    );
+    let (base_field, color_field) = split_color(field);
+    let pieces = color_field_to_pieces(color_field).unwrap();
+    let percent = find_percentage(base_field, vec![pieces.clone()], &dont_care, no_hold);
+    assert!((percent - 100.0 / 3.0).abs() < 1e-9);
+
+    let hold = PercentageOptions::new(true, false, false);
+    let percent = find_percentage(base_field, vec![pieces], &dont_care, hold);
+    assert!((percent - 200.0 / 3.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_placement_graph() {
+    use crate::field::{apply_garbage, split_color, PercentageOptions};
+    use crate::piece::dependency::PlacementGraph;
+
+    let no_softdrop = PercentageOptions::new(false, false, false);
+    let softdrop = PercentageOptions::new(false, true, false);
+    let dont_care = [[0; 10]; 24];
+
+    // the J only rests on the I, the O stands on the floor
+    let field = apply_garbage(
+        field_from_rows(&["J.........", "JJJ.....OO", "IIII....OO"]),
+        0,
+    );
+    let (base_field, color_field) = split_color(field);
+    let pieces = color_field_to_pieces(color_field).unwrap();
+    let i = pieces
+        .iter()
+        .position(|p| p.piece_type == PieceType::I)
+        .unwrap();
+    let j = pieces
+        .iter()
+        .position(|p| p.piece_type == PieceType::J)
+        .unwrap();
+    let o = pieces
+        .iter()
+        .position(|p| p.piece_type == PieceType::O)
+        .unwrap();
+
+    let graph = PlacementGraph::new(&pieces, &base_field, &dont_care, no_softdrop);
+    assert!(graph.predecessors(j) == [i]);
+    assert!(graph.predecessors(i).is_empty());
+    assert!(graph.predecessors(o).is_empty());
+    assert!(graph.comes_before(i, j) && !graph.comes_before(j, i));
+    let order = graph.find_order().unwrap();
+    assert!(order.iter().position(|n| *n == i) < order.iter().position(|n| *n == j));
+    assert!(graph.held_up_by_later_pieces().is_empty());
+
+    // softdrop doesn't help a piece with nothing under it
+    let graph = PlacementGraph::new(&pieces, &base_field, &dont_care, softdrop);
+    assert!(graph.predecessors(j) == [i]);
+
+    // a floating piece never gets placed
+    let field = apply_garbage(
+        field_from_rows(&["J.........", "JJJ.......", ".........."]),
+        0,
+    );
+    let (base_field, color_field) = split_color(field);
+    let pieces = color_field_to_pieces(color_field).unwrap();
+    let graph = PlacementGraph::new(&pieces, &base_field, &dont_care, no_softdrop);
+    assert!(graph.find_order().is_none());
+}
+
+#[test]
+fn test_dot_export() {
+    use crate::field::{apply_garbage, split_color, PercentageOptions};
+    use crate::piece::dependency::PlacementGraph;
+
+    let options = PercentageOptions::new(false, false, false);
+    let field = apply_garbage(
+        field_from_rows(&["J.........", "JJJ.......", "IIII......"]),
+        0,
+    );
+    let (base_field, color_field) = split_color(field);
+    let pieces = color_field_to_pieces(color_field).unwrap();
+    let graph = PlacementGraph::new(&pieces, &base_field, &[[0; 10]; 24], options);
+    let dot = graph.to_dot("setup");
+
+    let i = pieces
+        .iter()
+        .position(|p| p.piece_type == PieceType::I)
+        .unwrap();
+    let j = pieces
+        .iter()
+        .position(|p| p.piece_type == PieceType::J)
+        .unwrap();
+    assert!(dot.starts_with("digraph setup {\n"));
+    assert!(dot.contains(&format!(
+        "p{} [label=\"I ({}, {})\"];",
+        i, pieces[i].position.0, pieces[i].position.1
+    )));
+    assert!(dot.contains(&format!(
+        "p{} -> p{} [label=\"support\", style=solid];",
+        i, j
+    )));
+    assert!(dot.contains(&format!(
+        "p{} -> p{} [label=\"harddrop\", style=dashed];",
+        i, j
+    )));
+    assert!(dot.ends_with("}\n"));
+}
+
+#[test]
+fn test_spawn() {
+    use crate::field::PercentageOptions;
+    use crate::piece::lock_delay::LockDelay;
+    use crate::piece::rotation_system::RotationSystem;
+    use crate::piece::spawn::*;
+
+    let srs = RotationSystem::Srs;
+    let mut field = [[0; 10]; 24];
+    assert!(srs.spawn_piece(PieceType::O).position == (4, 2));
+    assert!(!blocked_out(PieceType::T, &field, srs));
+
+    // a piece locking completely in the buffer zone tops out
+    let mut piece = srs.spawn_piece(PieceType::T);
+    assert!(locked_out(piece));
+    piece.position.1 += 1;
+    assert!(!locked_out(piece));
+
+    let o = Piece {
+        piece_type: PieceType::O,
+        rotation: Rotation::Normal,
+        position: (0, 22),
+    };
+    assert!(can_harddrop(o, &field, srs));
+
+    // a wall reaching into the spawn rows stops the O sliding over
+    for row in field.iter_mut().skip(3) {
+        row[2] = 8;
+    }
+    assert!(!can_harddrop(o, &field, srs));
+    assert!(!softdrop_from_spawn(o, &field, srs, LockDelay::Infinite));
+    field[3][2] = 0;
+    assert!(can_harddrop(o, &field, srs));
+
+    field[3][4] = 8;
+    assert!(blocked_out(PieceType::T, &field, srs));
+    assert!(!can_harddrop(o, &field, srs));
+
+    // ARS spawns the T pointing down, so it isn't in the way of that
+    let mut field = [[0; 10]; 24];
+    field[3][3] = 8;
+    assert!(blocked_out(PieceType::T, &field, srs));
+    assert!(!blocked_out(PieceType::T, &field, RotationSystem::Ars));
+
+    // the O has to be tucked under the overhang
+    let mut field = [[0; 10]; 24];
+    field[21][1] = 8;
+    let no_softdrop = PercentageOptions::new(false, false, false);
+    let softdrop = PercentageOptions::new(false, true, false);
+    assert!(!piece_can_be_placed(o, &field, no_softdrop));
+    assert!(piece_can_be_placed(o, &field, softdrop));
+}
+
+#[test]
+fn test_spins() {
+    use crate::field::apply_garbage;
+    use crate::piece::gravity::Gravity;
+    use crate::piece::lock_delay::LockDelay;
+    use crate::piece::movement::*;
+    use crate::piece::rotation_system::RotationSystem;
+
+    let srs = RotationSystem::Srs;
+
+    // a TSD slot under an overhang
+    let field = apply_garbage(
+        field_from_rows(&["..XXXXXXXX", "...XXXXXXX", "X.XXXXXXXX"]),
+        0,
+    );
+    assert!(tspin_available(
+        &field,
+        srs,
+        LockDelay::Infinite,
+        Gravity::Normal
+    ));
+
+    let tsd = Piece {
+        piece_type: PieceType::T,
+        rotation: Rotation::Double,
+        position: (0, 21),
+    };
+    assert!(
+        placement_spin(tsd, &field, true, srs, LockDelay::Infinite, Gravity::Normal)
+            == Some(Spin::Full)
+    );
+    assert!(placement_spin(
+        tsd,
+        &field,
+        false,
+        srs,
+        LockDelay::Infinite,
+        Gravity::Normal
+    )
+    .is_none());
+    assert!(!can_harddrop(tsd, &field, srs));
+
+    // without the overhang the T just drops in
+    let field = apply_garbage(
+        field_from_rows(&["...XXXXXXX", "...XXXXXXX", "X.XXXXXXXX"]),
+        0,
+    );
+    assert!(!tspin_available(
+        &field,
+        srs,
+        LockDelay::Infinite,
+        Gravity::Normal
+    ));
+    assert!(
+        placement_spin(tsd, &field, true, srs, LockDelay::Infinite, Gravity::Normal)
+            == Some(Spin::NotSpun)
+    );
+
+    // an S twisted into a spot it can't get back out of
+    let field = apply_garbage(
+        field_from_rows(&["X..XXXXXXX", "..XXXXXXXX", "XXXXXXXXXX"]),
+        0,
+    );
+    let s = Piece {
+        piece_type: PieceType::S,
+        rotation: Rotation::Double,
+        position: (0, 20),
+    };
+    assert!(
+        placement_spin(s, &field, true, srs, LockDelay::Infinite, Gravity::Normal)
+            == Some(Spin::Immobile)
+    );
+}
+
+#[test]
+fn test_rotation_systems() {
+    use crate::piece::movement::{rotate, Turn};
+    use crate::piece::rotation_system::RotationSystem;
+
+    assert!(RotationSystem::from_name("SRS+") == Ok(RotationSystem::SrsPlus));
+    assert!(RotationSystem::from_name("dtet").is_err());
+
+    // ARS and NRS Ts spawn pointing down
+    let t = RotationSystem::Ars.spawn_piece(PieceType::T);
+    assert!(piece_block_positions(t) == Some(vec![(3, 2), (4, 2), (5, 2), (4, 3)]));
+
+    // an upright I against the right wall has to kick to lie down
+    let field = [[0; 10]; 24];
+    let i = Piece {
+        piece_type: PieceType::I,
+        rotation: Rotation::Right,
+        position: (7, 10),
+    };
+    let (kicked, kick) = rotate(i, Turn::Clockwise, &field, RotationSystem::Srs).unwrap();
+    assert!(kick == 1 && kicked.position == (6, 10));
+    assert!(rotate(i, Turn::Clockwise, &field, RotationSystem::Nrs).is_none());
+    assert!(rotate(i, Turn::Clockwise, &field, RotationSystem::Ars).is_none());
+
+    // ARS won't kick when the middle column is in the way first
+    let mut field = [[0; 10]; 24];
+    field[10][4] = 8;
+    let t = Piece {
+        piece_type: PieceType::T,
+        rotation: Rotation::Double,
+        position: (3, 10),
+    };
+    assert!(rotate(t, Turn::Clockwise, &field, RotationSystem::Ars).is_none());
+    let mut field = [[0; 10]; 24];
+    field[11][3] = 8;
+    let (kicked, _) = rotate(t, Turn::Clockwise, &field, RotationSystem::Ars).unwrap();
+    assert!(kicked.position == (4, 10));
+
+    // SRS+ can turn the T around in place
+    assert!(RotationSystem::SrsPlus.has_half_turns() && !RotationSystem::Srs.has_half_turns());
+    let t = Piece {
+        piece_type: PieceType::T,
+        rotation: Rotation::Normal,
+        position: (3, 10),
+    };
+    let (turned, kick) = rotate(t, Turn::Half, &[[0; 10]; 24], RotationSystem::SrsPlus).unwrap();
+    assert!(kick == 0 && turned.rotation == Rotation::Double && turned.position == (3, 10));
+}
+
+#[test]
+fn test_ruleset_file() {
+    use crate::piece::movement::{rotate, Turn};
+    use crate::piece::rotation_system::RotationSystem;
+    use crate::piece::ruleset::Ruleset;
+
+    // the example ruleset is just SRS
+    let ruleset = Ruleset::from_toml(include_str!("../rulesets/srs.toml")).unwrap();
+    let custom = RotationSystem::Custom(&ruleset);
+    let srs = RotationSystem::Srs;
+    let piece_types = [
+        PieceType::S,
+        PieceType::Z,
+        PieceType::L,
+        PieceType::J,
+        PieceType::T,
+        PieceType::O,
+        PieceType::I,
+    ];
+    let rotations = [
+        Rotation::Normal,
+        Rotation::Right,
+        Rotation::Double,
+        Rotation::Left,
+    ];
+    for piece_type in piece_types.iter() {
+        let spawn = custom.spawn_piece(*piece_type);
+        assert!(
+            piece_block_positions(spawn) == piece_block_positions(srs.spawn_piece(*piece_type))
+        );
+        for rotation in rotations.iter() {
+            assert!(custom.state_offset(*piece_type, *rotation) == (0, 0));
+            for to in [rotation.clockwise(), rotation.counter_clockwise()].iter() {
+                assert!(
+                    custom.kicks(*piece_type, *rotation, *to)
+                        == srs.kicks(*piece_type, *rotation, *to)
+                );
+            }
+        }
+    }
+
+    // two state pieces and kicks written in JSON
+    let mut json = String::from("{\"pieces\": {");
+    for (name, shape) in [
+        (
+            "S",
+            "[\"...\", \".##\", \"##.\"], [\"#..\", \"##.\", \".#.\"]",
+        ),
+        (
+            "Z",
+            "[\"...\", \"##.\", \".##\"], [\"..#\", \".##\", \".#.\"]",
+        ),
+        ("L", "[\"..#\", \"###\", \"...\"]"),
+        ("J", "[\"#..\", \"###\", \"...\"]"),
+        ("T", "[\".#.\", \"###\", \"...\"]"),
+        ("O", "[\"##\", \"##\"]"),
+        ("I", "[\"....\", \"####\", \"....\", \"....\"]"),
+    ]
+    .iter()
+    {
+        json.push_str(&format!(
+            "\"{}\": {{\"states\": [{}], \"spawn\": [3, 1], \"kicks\": {{\"01\": [[0, 0], [1, 0]]}}}},",
+            name, shape
+        ));
+    }
+    json.pop();
+    json.push_str("}}");
+    let ruleset = Ruleset::from_json(&json).unwrap();
+    let custom = RotationSystem::Custom(&ruleset);
+
+    let s = custom.spawn_piece(PieceType::S);
+    assert!(piece_block_positions(s) == Some(vec![(4, 2), (5, 2), (3, 3), (4, 3)]));
+    let (turned, _) = rotate(s, Turn::Clockwise, &[[0; 10]; 24], custom).unwrap();
+    assert!(piece_block_positions(turned) == Some(vec![(3, 1), (3, 2), (4, 2), (4, 3)]));
+    let (back, _) = rotate(turned, Turn::Clockwise, &[[0; 10]; 24], custom).unwrap();
+    assert!(piece_block_positions(back) == piece_block_positions(s));
+
+    // the kick listed for the first turn gets used
+    let mut field = [[0; 10]; 24];
+    field[1][3] = 8;
+    let (kicked, kick) = rotate(s, Turn::Clockwise, &field, custom).unwrap();
+    assert!(kick == 1 && kicked.position.0 == turned.position.0 + 1);
+
+    assert!(Ruleset::from_json("{\"pieces\": {}}").is_err());
+    let bad = json.replace("[\".#.\", \"###\", \"...\"]", "[\".#.\", \"##.\", \"#..\"]");
+    // custom shapes aren't supported
+    assert!(Ruleset::from_json(&bad)
+        .unwrap_err()
+        .contains("can't change piece shapes"));
+}
+
+#[test]
+fn test_finesse() {
+    use crate::field::{apply_garbage, PercentageOptions};
+    use crate::piece::finesse::{finesse, Input};
+    use crate::piece::movement::drop_down;
+    use crate::piece::rotation_system::RotationSystem;
+
+    let options = PercentageOptions::new(true, true, true);
+    let empty = [[0; 10]; 24];
+
+    // a T dropped straight down from where it spawns
+    let t = drop_down(RotationSystem::Srs.spawn_piece(PieceType::T), &empty);
+    assert_eq!(finesse(t, &empty, options), Some(vec![Input::HardDrop]));
+
+    // an O against the left wall
+    let o = drop_down(
+        RotationSystem::Srs
+            .spawn_piece(PieceType::O)
+            .clone_with_offset(-4, 0),
+        &empty,
+    );
+    assert_eq!(
+        finesse(o, &empty, options),
+        Some(vec![Input::DasLeft, Input::HardDrop])
+    );
+    let no_das = PercentageOptions {
+        das: false,
+        ..options
+    };
+    assert_eq!(
+        finesse(o, &empty, no_das),
+        Some(vec![
+            Input::Left,
+            Input::Left,
+            Input::Left,
+            Input::Left,
+            Input::HardDrop
+        ])
+    );
+
+    // the TSD slot needs a soft drop and a spin
+    let field = apply_garbage(
+        field_from_rows(&["..XXXXXXXX", "...XXXXXXX", "X.XXXXXXXX"]),
+        0,
+    );
+    let tsd = Piece {
+        piece_type: PieceType::T,
+        rotation: Rotation::Double,
+        position: (0, 21),
+    };
+    assert_eq!(
+        finesse(tsd, &field, options),
+        Some(vec![
+            Input::Clockwise,
+            Input::DasLeft,
+            Input::SoftDrop,
+            Input::Clockwise,
+            Input::HardDrop
+        ])
+    );
+    let no_soft_drop = PercentageOptions::new(true, false, true);
+    assert_eq!(finesse(tsd, &field, no_soft_drop), None);
+}
+
+#[test]
+fn test_trace_queue() {
+    use crate::field::{trace_queue, PercentageOptions};
+
+    let (base_field, pieces) = jiz_setup();
+    let no_dont_care = [[0; 10]; 24];
+    let setups = vec![pieces.clone()];
+    let trace = |queue: &[PieceType], options| {
+        trace_queue(base_field, &setups, &no_dont_care, options, queue)
+    };
+    let placed = |order: &[crate::piece::Placement]| {
+        order
+            .iter()
+            .map(|(piece, _, _)| piece.piece_type)
+            .collect::<Vec<PieceType>>()
+    };
+    let in_setup = |piece: &Piece| {
+        pieces.iter().any(|p| {
+            p.piece_type == piece.piece_type
+                && p.rotation == piece.rotation
+                && p.position == piece.position
+        })
+    };
+
+    // Z goes first, then the J is held until the I is tucked under the Z
+    let queue = [PieceType::Z, PieceType::J, PieceType::I];
+    let (setup, order) = trace(&queue, PercentageOptions::new(true, true, false)).unwrap();
+    assert!(setup == 0);
+    assert!(placed(&order) == vec![PieceType::Z, PieceType::I, PieceType::J]);
+    assert!(order
+        .iter()
+        .all(|(piece, _, inputs)| in_setup(piece) && inputs.is_some()));
+    assert!(trace(&queue, PercentageOptions::new(false, true, false)).is_none());
+
+    // an extra piece placed out of the way is traced where it comes
+    let extra = PercentageOptions {
+        extra_pieces: 1,
+        place_extras: true,
+        ..PercentageOptions::new(false, true, false)
+    };
+    let queue = [PieceType::O, PieceType::I, PieceType::J, PieceType::Z];
+    let (_, order) = trace(&queue, extra).unwrap();
+    assert!(placed(&order) == queue.to_vec());
+    assert!(!in_setup(&order[0].0));
+    assert!(order[1..].iter().all(|(piece, _, _)| in_setup(piece)));
+}
+
+#[test]
+fn test_lock_delay() {
+    use crate::field::{apply_garbage, PercentageOptions};
+    use crate::piece::finesse::finesse;
+    use crate::piece::lock_delay::LockDelay;
+    use crate::piece::rotation_system::RotationSystem;
+    use crate::piece::spawn::softdrop_from_spawn;
+
+    // the O has to slide along the ground under the roof, falling down a
+    // step halfway
+    let field = apply_garbage(
+        field_from_rows(&[
+            "..XXXXXXXX",
+            "......XXXX",
+            "......XXXX",
+            "XXXX......",
+            "XXXX......",
+        ]),
+        0,
+    );
+    let o = Piece {
+        piece_type: PieceType::O,
+        rotation: Rotation::Normal,
+        position: (8, 22),
+    };
+    let move_reset = |limit, step_reset| LockDelay::MoveReset { limit, step_reset };
+    let srs = RotationSystem::Srs;
+
+    assert!(softdrop_from_spawn(o, &field, srs, LockDelay::Infinite));
+    assert!(softdrop_from_spawn(o, &field, srs, move_reset(15, false)));
+    assert!(softdrop_from_spawn(o, &field, srs, move_reset(4, true)));
+    assert!(!softdrop_from_spawn(o, &field, srs, move_reset(4, false)));
+    assert!(!softdrop_from_spawn(o, &field, srs, move_reset(3, true)));
+
+    // the move search behind spins runs out of moves the same way
+    let mut options = PercentageOptions::new(true, true, true);
+    assert!(piece_can_be_placed(o, &field, options));
+    options.lock_delay = move_reset(4, false);
+    assert!(!piece_can_be_placed(o, &field, options));
+    options.lock_delay = move_reset(4, true);
+    assert!(piece_can_be_placed(o, &field, options));
+
+    // and so do the fewest inputs behind --maxinputs
+    options.lock_delay = move_reset(4, false);
+    assert!(finesse(o, &field, options).is_none());
+    options.lock_delay = move_reset(4, true);
+    assert!(finesse(o, &field, options).is_some());
+}
+
+#[test]
+fn test_twenty_g() {
+    use crate::field::{apply_garbage, PercentageOptions};
+    use crate::piece::finesse::{finesse, Input};
+    use crate::piece::gravity::Gravity;
+
+    let normal = PercentageOptions::new(true, true, true);
+    let twenty_g = PercentageOptions {
+        gravity: Gravity::TwentyG {
+            irs: false,
+            ihs: false,
+        },
+        ..normal
+    };
+
+    // the O can't be moved over a block on the floor
+    let field = apply_garbage(field_from_rows(&["..X......."]), 0);
+    let o = Piece {
+        piece_type: PieceType::O,
+        rotation: Rotation::Normal,
+        position: (0, 22),
+    };
+    assert!(piece_can_be_placed(o, &field, normal));
+    assert!(!piece_can_be_placed(o, &field, twenty_g));
+
+    // or slid under a roof past a hole it falls into
+    let field = apply_garbage(
+        field_from_rows(&["..XXXXXXXX", "..........", "..........", "XX..XXXXXX"]),
+        0,
+    );
+    let o = Piece {
+        piece_type: PieceType::O,
+        rotation: Rotation::Normal,
+        position: (6, 21),
+    };
+    assert!(piece_can_be_placed(o, &field, normal));
+    assert!(!piece_can_be_placed(o, &field, twenty_g));
+
+    // turning a T on the floor kicks it over, unless it's turned before it
+    // lands
+    let empty = [[0; 10]; 24];
+    let t = Piece {
+        piece_type: PieceType::T,
+        rotation: Rotation::Right,
+        position: (3, 21),
+    };
+    assert_eq!(
+        finesse(t, &empty, twenty_g),
+        Some(vec![Input::Right, Input::Clockwise, Input::HardDrop])
+    );
+    let irs = PercentageOptions {
+        gravity: Gravity::TwentyG {
+            irs: true,
+            ihs: false,
+        },
+        ..normal
+    };
+    assert_eq!(
+        finesse(t, &empty, irs),
+        Some(vec![Input::Clockwise, Input::HardDrop])
+    );
+}
+
+#[test]
+fn test_max_inputs() {
+    use crate::field::{
+        apply_garbage, find_percentage, find_working_queues, split_color, PercentageOptions,
+    };
+
+    // the T drops straight in, the O has to be moved to the wall first
+    let field = apply_garbage(field_from_rows(&["OO..T.....", "OO.TTT...."]), 0);
+    let (base_field, color_field) = split_color(field);
+    let pieces = color_field_to_pieces(color_field).unwrap();
+    let no_dont_care = [[0; 10]; 24];
+
+    let options = PercentageOptions::new(false, true, true);
+    let queues = find_working_queues(base_field, vec![pieces.clone()], &no_dont_care, options);
+    assert!(queues.len() == 2 && queues.iter().all(|(_, works)| *works));
+
+    let budget = |max_inputs| PercentageOptions {
+        max_inputs: Some(max_inputs),
+        ..options
+    };
+    assert!(find_percentage(base_field, vec![pieces.clone()], &no_dont_care, budget(0)) == 0.0);
+    assert!(find_percentage(base_field, vec![pieces], &no_dont_care, budget(1)) == 100.0);
+}
+
+#[test]
+fn test_previews() {
+    use crate::field::{apply_garbage, find_percentage, split_color, PercentageOptions};
+    use crate::preview::find_preview_percentage;
+
+    let no_dont_care = [[0; 10]; 24];
+
+    // the I and the O go in opposite orders in the two setups, and the T's
+    // spot gives away which setup is being built
+    let setups = [
+        ["OO........", "OO....T...", "IIII.TTT.."],
+        ["IIII......", "OO.....T..", "OO....TTT."],
+    ]
+    .iter()
+    .map(|rows| {
+        let (_, color_field) = split_color(apply_garbage(field_from_rows(rows), 0));
+        color_field_to_pieces(color_field).unwrap()
+    })
+    .collect::<Vec<Vec<Piece>>>();
+    let base_field = [[0; 10]; 24];
+
+    let options = PercentageOptions::new(false, false, false);
+    let percent = find_percentage(base_field, setups.clone(), &no_dont_care, options);
+    assert!(percent == 100.0);
+
+    // seeing the whole queue is the same as knowing it
+    let preview = |previews| {
+        find_preview_percentage(base_field, setups.clone(), &no_dont_care, options, previews)
+    };
+    assert!(preview(2) == percent);
+    assert!(preview(1) == percent);
+
+    // a T first has to be placed before seeing whether the I or the O comes
+    // next, which goes wrong half the time
+    assert!((preview(0) - 500.0 / 6.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_randomizers() {
+    use crate::bag::Bag;
+    use crate::field::{find_percentage, PercentageOptions};
+    use crate::perm_gen::generate_perm_iter;
+    use crate::randomizer::{from_name, Randomizer};
+
+    // every randomizer deals some queue of two pieces
+    for name in ["7bag", "14bag", "random", "tgm1", "tgm3", "nes"].iter() {
+        let randomizer = from_name(name).unwrap();
+        let total = PIECE_TYPES
+            .iter()
+            .flat_map(|a| PIECE_TYPES.iter().map(move |b| vec![*a, *b]))
+            .map(|queue| randomizer.queue_chance(&queue))
+            .sum::<f64>();
+        assert!((total - 1.0).abs() < 1e-9, "{}", name);
+    }
+
+    let bag = Bag::new(1);
+    let all_orders = generate_perm_iter(PIECE_TYPES.to_vec())
+        .map(|queue| bag.queue_chance(&queue))
+        .collect::<Vec<f64>>();
+    assert!(
+        all_orders.len() == 5040 && all_orders.iter().all(|c| (c - 1.0 / 5040.0).abs() < 1e-12)
+    );
+    assert!(bag.queue_chance(&[PieceType::S, PieceType::S]) == 0.0);
+    let s_twice = Bag::new(2).queue_chance(&[PieceType::S, PieceType::S]);
+    assert!((s_twice - 2.0 / 14.0 / 13.0).abs() < 1e-12);
+
+    // TGM never starts with an S, Z or O, and the NES rarely repeats
+    let tgm = from_name("tgm1").unwrap();
+    assert!(tgm.queue_chance(&[PieceType::S]) == 0.0);
+    let nes = from_name("nes").unwrap();
+    assert!((nes.queue_chance(&[PieceType::T, PieceType::T]) - 1.0 / 7.0 / 28.0).abs() < 1e-12);
+
+    // only bags can start partway through one
+    assert!(from_name("14Bag").unwrap().bag_repeats() == Some(2));
+    assert!(nes.bag_repeats().is_none());
+
+    let (base_field, pieces) = jiz_setup();
+    let no_dont_care = [[0; 10]; 24];
+    let percent = |name| {
+        let randomizer = from_name(name).unwrap();
+        let options = PercentageOptions {
+            randomizer: Some(&*randomizer),
+            ..PercentageOptions::new(false, false, false)
+        };
+        find_percentage(base_field, vec![pieces.clone()], &no_dont_care, options)
+    };
+
+    // a bag deals every order of its pieces as often, TGM never deals the
+    // queues starting with the Z
+    assert!((percent("7bag") - 100.0 / 3.0).abs() < 1e-9);
+    assert!((percent("tgm1") - 50.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_bag_offset() {
+    use crate::bag::Bag;
+    use crate::field::{deal_chance, find_working_queues, percentage, PercentageOptions};
+    use crate::randomizer::Randomizer;
+
+    let used = parse_queue("TOS").unwrap();
+    let bag = Bag::with_used(1, &used).unwrap();
+    assert!(Bag::with_used(1, &parse_queue("TT").unwrap()).is_err());
+    assert!(Bag::with_used(2, &parse_queue("TT").unwrap()).is_ok());
+
+    // the four pieces left come first, then the next bag
+    let queue = parse_queue("ZJILT").unwrap();
+    assert!((bag.queue_chance(&queue) - 1.0 / 24.0 / 7.0).abs() < 1e-12);
+    assert!(bag.queue_chance(&parse_queue("T").unwrap()) == 0.0);
+    assert!(bag.drawn_from_next(3) == 0 && bag.drawn_from_next(6) == 2);
+
+    let (base_field, pieces) = jiz_setup();
+    let no_dont_care = [[0; 10]; 24];
+    let mut options = PercentageOptions::new(false, false, false);
+    let uniform = percentage(
+        &find_working_queues(base_field, vec![pieces.clone()], &no_dont_care, options),
+        options,
+    );
+
+    // J, I and Z are three of the four pieces left, in any order
+    options.randomizer = Some(&bag);
+    let queues = find_working_queues(base_field, vec![pieces.clone()], &no_dont_care, options);
+    assert!((percentage(&queues, options) - uniform).abs() < 1e-9);
+    assert!((deal_chance(&queues, &bag) - 25.0).abs() < 1e-9);
+
+    // with the I already used the setup can't be built from this bag
+    let without_i = Bag::with_used(1, &parse_queue("IOS").unwrap()).unwrap();
+    options.randomizer = Some(&without_i);
+    let queues = find_working_queues(base_field, vec![pieces.clone()], &no_dont_care, options);
+    assert!(percentage(&queues, options) == 0.0);
+
+    // with only J, I and Z left and nothing drawn from the next bag, the
+    // extra pieces never come
+    let last_three = Bag::with_used(1, &parse_queue("TOSL").unwrap()).unwrap();
+    assert!(last_three.extras_within(3, 2, 0) == Ok(0));
+    assert!(last_three.extras_within(3, 2, 1) == Ok(1));
+    assert!(last_three.extras_within(4, 0, 0).is_err());
+    options.randomizer = Some(&last_three);
+    options.extra_pieces = last_three.extras_within(3, 2, 0).unwrap();
+    let queues = find_working_queues(base_field, vec![pieces.clone()], &no_dont_care, options);
+    assert!(queues.len() == 6 && queues.iter().all(|(queue, _)| queue.len() == 3));
+    assert!((percentage(&queues, options) - uniform).abs() < 1e-9);
+
+    // one piece from the next bag comes after the three
+    options.extra_pieces = last_three.extras_within(3, 2, 1).unwrap();
+    let queues = find_working_queues(base_field, vec![pieces], &no_dont_care, options);
+    assert!(queues.iter().all(|(queue, _)| queue.len() == 4));
+    assert!((deal_chance(&queues, &last_three) - 100.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_initial_hold() {
+    use crate::field::{find_percentage, find_working_queues, unknown_pieces, PercentageOptions};
+
+    let (base_field, pieces) = jiz_setup();
+    let no_dont_care = [[0; 10]; 24];
+    let hold = PercentageOptions::new(true, false, false);
+    let percent =
+        |options| find_percentage(base_field, vec![pieces.clone()], &no_dont_care, options);
+
+    let holding = |piece_type| PercentageOptions {
+        initial_hold: Some(piece_type),
+        ..hold
+    };
+    assert!(percent(holding(PieceType::I)) == 100.0);
+    assert!(percent(holding(PieceType::J)) == 50.0);
+    let queues = find_working_queues(
+        base_field,
+        vec![pieces.clone()],
+        &no_dont_care,
+        holding(PieceType::J),
+    );
+    assert!(
+        queues
+            == vec![
+                (parse_queue("ZI").unwrap(), false),
+                (parse_queue("IZ").unwrap(), true)
+            ]
+    );
+
+    let known = |queue| PercentageOptions {
+        known_queue: queue,
+        ..hold
+    };
+    assert!(percent(known(&[PieceType::I])) == 100.0);
+    assert!(percent(known(&[PieceType::Z])) == 50.0);
+    assert!(percent(known(&[PieceType::Z, PieceType::J])) == 0.0);
+
+    // the held piece has to be one of the setup's and needs hold to be used
+    assert!(unknown_pieces(&pieces, holding(PieceType::T)).is_err());
+    let no_hold = PercentageOptions {
+        hold: false,
+        ..holding(PieceType::I)
+    };
+    assert!(unknown_pieces(&pieces, no_hold).is_err());
+}
+
+#[test]
+fn test_hold_rules() {
+    use crate::field::PercentageOptions;
+    use crate::field::{find_percentage, unknown_pieces};
+    use crate::hold_comp::{implied_by, FinalHold, HoldRules};
+    use crate::preview::find_preview_percentage;
+
+    // once hold is used it never empties
+    let empty = HoldRules {
+        final_hold: FinalHold::Empty,
+        ..HoldRules::default()
+    };
+    let queue = parse_queue("IJZ").unwrap();
+    assert!(implied_by(&queue, None, 3, false, &empty) == vec![queue.clone()]);
+
+    let (base_field, pieces) = jiz_setup();
+    let no_dont_care = [[0; 10]; 24];
+    let with_rules = |hold_rules| PercentageOptions {
+        hold_rules,
+        ..PercentageOptions::new(true, false, false)
+    };
+    let near = |a: f64, b: f64| (a - b).abs() < 1e-9;
+    let percent = |hold_rules| {
+        let options = with_rules(hold_rules);
+        let all_seen =
+            find_preview_percentage(base_field, vec![pieces.clone()], &no_dont_care, options, 6);
+        let percent = find_percentage(base_field, vec![pieces.clone()], &no_dont_care, options);
+        assert!(near(all_seen, percent));
+        percent
+    };
+
+    // the I can be held past as long as hold gets used twice to place both
+    assert!(near(percent(HoldRules::default()), 200.0 / 3.0));
+    let no_first = HoldRules {
+        first_piece: false,
+        ..HoldRules::default()
+    };
+    assert!(near(percent(no_first), 100.0 / 3.0));
+    let at_most = |uses| HoldRules {
+        max_uses: Some(uses),
+        ..HoldRules::default()
+    };
+    assert!(near(percent(at_most(1)), 100.0 / 3.0));
+    assert!(near(percent(at_most(2)), 200.0 / 3.0));
+    assert!(near(percent(empty), 100.0 / 3.0));
+
+    // keeping a T for later draws one more piece
+    let keep_t = HoldRules {
+        final_hold: FinalHold::Piece(PieceType::T),
+        ..HoldRules::default()
+    };
+    assert!(near(percent(keep_t), 50.0));
+    let no_hold = PercentageOptions {
+        hold: false,
+        ..with_rules(keep_t)
+    };
+    assert!(unknown_pieces(&pieces, no_hold).is_err());
+}
+
+#[test]
+fn test_leftover_report() {
+    use crate::bag::Bag;
+    use crate::field::{apply_garbage, split_color, PercentageOptions};
+    use crate::leftover::{leftover_pieces, leftover_report};
+
+    let setup = |rows: &[&str]| {
+        let (_, color_field) = split_color(apply_garbage(field_from_rows(rows), 0));
+        color_field_to_pieces(color_field).unwrap()
+    };
+    let groups = vec![
+        vec![setup(&["IIII......"])],
+        vec![setup(&["OO........", "OO........"])],
+    ];
+    let no_dont_care = [[0; 10]; 24];
+    let near = |a: f64, b: f64| (a - b).abs() < 1e-9;
+    let rest_of_bag = |used| {
+        let mut rest = Bag::with_used(1, &parse_queue(used).unwrap())
+            .unwrap()
+            .after(&[]);
+        rest.sort_unstable();
+        rest
+    };
+
+    // without hold the first piece decides which setup gets built, out of
+    // the queues starting with an I or an O
+    let no_hold = PercentageOptions::new(false, true, true);
+    let report = leftover_report([[0; 10]; 24], &groups, &no_dont_care, no_hold, &Bag::new(1));
+    assert!(report.len() == 2);
+    assert!(report
+        .iter()
+        .any(|(leftover, _)| *leftover == rest_of_bag("O")));
+    assert!(report.iter().all(|(_, percent)| near(*percent, 50.0)));
+    let pieces = leftover_pieces(&report);
+    assert!(pieces.len() == 7);
+    assert!(pieces.iter().all(|(piece_type, percent)| match piece_type {
+        PieceType::I | PieceType::O => near(*percent, 50.0),
+        _ => near(*percent, 100.0),
+    }));
+
+    // with hold the first piece can be kept in hold, it's still left over.
+    // Queues starting with both I and O can build either setup, so they
+    // count half for each and the sets still add up to 100%
+    let hold = PercentageOptions::new(true, true, true);
+    let report = leftover_report([[0; 10]; 24], &groups, &no_dont_care, hold, &Bag::new(1));
+    let leftover_i = report
+        .iter()
+        .find(|(leftover, _)| *leftover == rest_of_bag("I"));
+    assert!(near(leftover_i.unwrap().1, 50.0));
+    assert!(near(
+        report.iter().map(|(_, percent)| percent).sum::<f64>(),
+        100.0
+    ));
+
+    // the last piece of a bag leaves nothing over
+    let bag = Bag::with_used(1, &parse_queue("SZLJTI").unwrap()).unwrap();
+    let report = leftover_report([[0; 10]; 24], &groups, &no_dont_care, no_hold, &bag);
+    assert!(report == vec![(Vec::new(), 100.0)]);
+}
+
+#[test]
+fn test_extra_pieces() {
+    use crate::field::{apply_garbage, find_percentage, split_color, PercentageOptions};
+
+    let (base_field, pieces) = jiz_setup();
+    let no_dont_care = [[0; 10]; 24];
+    let near = |a: f64, b: f64| (a - b).abs() < 1e-9;
+    let percent_on = |base_field, hold, place_extras| {
+        let options = PercentageOptions {
+            extra_pieces: 1,
+            place_extras,
+            ..PercentageOptions::new(hold, false, false)
+        };
+        find_percentage(base_field, vec![pieces.clone()], &no_dont_care, options)
+    };
+    let percent = |hold, place_extras| percent_on(base_field, hold, place_extras);
+
+    // of the 132 queues of I, J, Z and one more piece, only IJZ and IZJ
+    // followed by the extra piece work without hold
+    assert!(near(percent(false, false), 100.0 * 14.0 / 132.0));
+    // placing the extra piece next to the setup lets it come before the J
+    // and Z too
+    assert!(near(percent(false, true), 100.0 * 48.0 / 132.0));
+    // hold can take the extra piece but it's stuck there
+    assert!(percent(true, false) > percent(false, false));
+    assert!(percent(true, true) > percent(true, false));
+
+    // a setup across the whole width still has room for the extra piece
+    // on top, once there's something under it
+    let (_, color_field) = split_color(apply_garbage(
+        field_from_rows(&["J........L", "JJJIIIILLL"]),
+        0,
+    ));
+    let wide = color_field_to_pieces(color_field).unwrap();
+    let wide_percent = |place_extras| {
+        let options = PercentageOptions {
+            extra_pieces: 1,
+            place_extras,
+            ..PercentageOptions::new(false, false, false)
+        };
+        find_percentage([[0; 10]; 24], vec![wide.clone()], &no_dont_care, options)
+    };
+    // the extra piece has to come last, or anywhere but first with
+    // place_extras
+    assert!(near(wide_percent(false), 100.0 * 42.0 / 132.0));
+    assert!(near(wide_percent(true), 100.0 * 108.0 / 132.0));
+}
+
+#[test]
+fn test_monte_carlo() {
+    use crate::bag::Bag;
+    use crate::field::{find_percentage, PercentageOptions};
+    use crate::monte_carlo::estimate_percentage;
+    use crate::randomizer;
+
+    let (base_field, pieces) = jiz_setup();
+    let no_dont_care = [[0; 10]; 24];
+    let options = PercentageOptions::new(true, false, false);
+    let exact = find_percentage(base_field, vec![pieces.clone()], &no_dont_care, options);
+    let estimate = |options, samples, precision, seed| {
+        estimate_percentage(
+            base_field,
+            vec![pieces.clone()],
+            &no_dont_care,
+            options,
+            samples,
+            precision,
+            seed,
+        )
+    };
+
+    let first = estimate(options, 500, None, 1);
+    assert!(first.drawn == 500);
+    assert!(first.low <= exact && exact <= first.high);
+    // the same seed draws the same queues
+    assert!(estimate(options, 500, None, 1).percent == first.percent);
+
+    // stops once the interval is narrow enough
+    let rough = estimate(options, 100000, Some(5.0), 1);
+    assert!(rough.drawn < 100000 && rough.margin() <= 5.0);
+
+    // every queue drawn counts, weighted by how likely the bag is to deal
+    // it, so none are thrown away when the bag seldom deals the setup
+    let seven_bag = Bag::new(1);
+    let bag = PercentageOptions {
+        randomizer: Some(&seven_bag),
+        extra_pieces: 1,
+        ..options
+    };
+    let from_bag = estimate(bag, 2000, None, 1);
+    assert!(from_bag.drawn == 2000 && from_bag.effective > 0.0);
+    let exact = find_percentage(base_field, vec![pieces.clone()], &no_dont_care, bag);
+    assert!(from_bag.low <= exact && exact <= from_bag.high);
+
+    let nes = randomizer::from_name("nes").unwrap();
+    let from_nes = PercentageOptions {
+        randomizer: Some(nes.as_ref()),
+        ..options
+    };
+    let estimated = estimate(from_nes, 2000, None, 1);
+    assert!(estimated.effective > 1000.0);
+    let exact = find_percentage(base_field, vec![pieces.clone()], &no_dont_care, from_nes);
+    assert!(estimated.low <= exact && exact <= estimated.high);
+
+    // without a randomizer every distinct queue counts the same, even ones
+    // where the extra piece repeats one of the setup's
+    let extra = PercentageOptions {
+        extra_pieces: 1,
+        ..PercentageOptions::new(false, false, false)
+    };
+    let exact = find_percentage(base_field, vec![pieces.clone()], &no_dont_care, extra);
+    let with_extra = estimate(extra, 10000, None, 1);
+    assert!(with_extra.low <= exact && exact <= with_extra.high);
+}
+
+#[test]
+fn test_threads() {
+    use crate::field::{find_working_queues, PercentageOptions};
+
+    let (base_field, pieces) = jiz_setup();
+    let no_dont_care = [[0; 10]; 24];
+    let queues = |threads| {
+        let options = PercentageOptions {
+            extra_pieces: 1,
+            threads,
+            ..PercentageOptions::new(true, false, false)
+        };
+        find_working_queues(base_field, vec![pieces.clone()], &no_dont_care, options)
+    };
+
+    // the queues come back in the same order whatever they're split into
+    let one_thread = queues(1);
+    assert!(one_thread.len() == 132);
+    for threads in [2, 5, 200].iter() {
+        assert!(queues(*threads) == one_thread);
+    }
+}
+
+#[test]
+fn test_prefix_percentages() {
+    use crate::bag::Bag;
+    use crate::field::{find_working_queues, prefix_percentages, PercentageOptions};
+
+    let (base_field, pieces) = jiz_setup();
+    let no_dont_care = [[0; 10]; 24];
+    let options = PercentageOptions::new(true, false, false);
+    let queues = find_working_queues(base_field, vec![pieces.clone()], &no_dont_care, options);
+
+    let table = prefix_percentages(&queues, 2, options);
+    assert!(table.len() == 6);
+    assert!(table[..4].iter().all(|(_, percent, _)| *percent == 100.0));
+    assert!(table[4..]
+        .iter()
+        .all(|(prefix, percent, _)| { !prefix.contains(&PieceType::I) && *percent == 0.0 }));
+    assert!(table
+        .iter()
+        .all(|(_, _, share)| (*share - 100.0 / 6.0).abs() < 1e-9));
+
+    // when a bag deals I, J and Z in its first four pieces, each of the four
+    // is as likely to come first
+    let seven_bag = Bag::new(1);
+    let bag = PercentageOptions {
+        randomizer: Some(&seven_bag),
+        extra_pieces: 1,
+        ..options
+    };
+    let queues = find_working_queues(base_field, vec![pieces.clone()], &no_dont_care, bag);
+    let table = prefix_percentages(&queues, 1, bag);
+    assert!(table.len() == 7);
+    let share_of = |piece_type| {
+        table
+            .iter()
+            .find(|(prefix, _, _)| prefix[0] == piece_type)
+            .unwrap()
+            .2
+    };
+    assert!((share_of(PieceType::I) - 25.0).abs() < 1e-9);
+    assert!((share_of(PieceType::S) - 25.0 / 4.0).abs() < 1e-9);
+    let total = table.iter().map(|(_, _, share)| share).sum::<f64>();
+    assert!((total - 100.0).abs() < 1e-9);
 }
@@ -0,0 +1,272 @@
+use crate::fieldmatrix::FieldMatrix;
+use crate::piece::{
+    get_rotation_times, piece_block_positions, piece_in_region, piece_type_to_fumen_index, Piece,
+    PieceType, Rotation, PIECE_TYPES,
+};
+use std::collections::HashMap;
+
+// Finds every way to exactly cover the cells of a region (a mask where the
+// cells are 1) with some of the candidate pieces, using Knuth's Dancing Links.
+//
+// With piece_counts, each tiling uses exactly those pieces.
+pub fn tile_region(
+    region: &FieldMatrix,
+    candidates: &[Piece],
+    piece_counts: Option<&[PieceType]>,
+) -> Vec<Vec<Piece>> {
+    let mut cell_columns = HashMap::new();
+    for (y, row) in region.iter().enumerate() {
+        for (x, n) in row.iter().enumerate() {
+            if *n != 0 {
+                let column = cell_columns.len();
+                cell_columns.insert((x, y), column);
+            }
+        }
+    }
+
+    if let Some(counts) = piece_counts {
+        if counts.len() * 4 != cell_columns.len() {
+            return Vec::new();
+        }
+    }
+
+    // a candidate sticking out of the region can never be part of a tiling
+    let candidates = candidates
+        .iter()
+        .filter(|piece| piece_in_region(**piece, region))
+        .copied()
+        .collect::<Vec<Piece>>();
+
+    let rows = candidates
+        .iter()
+        .map(|piece| {
+            piece_block_positions(*piece)
+                .unwrap()
+                .iter()
+                .map(|position| cell_columns[position])
+                .collect::<Vec<usize>>()
+        })
+        .collect::<Vec<Vec<usize>>>();
+
+    let mut limits = [usize::MAX; 8];
+    if let Some(counts) = piece_counts {
+        limits = [0; 8];
+        for piece_type in counts.iter() {
+            limits[piece_type_to_fumen_index(*piece_type) as usize] += 1;
+        }
+    }
+
+    let mut dlx = Dlx::new(cell_columns.len(), &rows);
+    let mut solutions = Vec::new();
+    dlx.search(
+        &mut Vec::new(),
+        &mut |partial: &[usize], row: usize| {
+            let piece_type = candidates[row].piece_type;
+            let used = partial
+                .iter()
+                .filter(|r| candidates[**r].piece_type == piece_type)
+                .count();
+            used < limits[piece_type_to_fumen_index(piece_type) as usize]
+        },
+        &mut solutions,
+    );
+
+    solutions
+        .into_iter()
+        .map(|rows| rows.iter().map(|row| candidates[*row]).collect())
+        .collect()
+}
+
+// every placement of any piece lying completely inside a region
+pub fn region_candidates(region: &FieldMatrix) -> Vec<Piece> {
+    let rotations = [
+        Rotation::Normal,
+        Rotation::Right,
+        Rotation::Double,
+        Rotation::Left,
+    ];
+    let mut candidates = Vec::new();
+
+    for piece_type in PIECE_TYPES.iter() {
+        for rotation in rotations.iter().take(get_rotation_times(*piece_type)) {
+            for y in -3..24 {
+                for x in -3..10 {
+                    let piece = Piece {
+                        piece_type: *piece_type,
+                        rotation: *rotation,
+                        position: (x, y),
+                    };
+                    if piece_in_region(piece, region) {
+                        candidates.push(piece);
+                    }
+                }
+            }
+        }
+    }
+
+    candidates
+}
+
+// Node 0 is the root, nodes 1..=columns are the column headers and the rest
+// are the 1s of the matrix, linked into circular lists both ways.
+struct Dlx {
+    left: Vec<usize>,
+    right: Vec<usize>,
+    up: Vec<usize>,
+    down: Vec<usize>,
+    column: Vec<usize>,
+    row: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl Dlx {
+    fn new(columns: usize, rows: &[Vec<usize>]) -> Dlx {
+        let mut dlx = Dlx {
+            left: Vec::new(),
+            right: Vec::new(),
+            up: Vec::new(),
+            down: Vec::new(),
+            column: Vec::new(),
+            row: Vec::new(),
+            size: Vec::new(),
+        };
+
+        for i in 0..=columns {
+            dlx.left.push(if i == 0 { columns } else { i - 1 });
+            dlx.right.push(if i == columns { 0 } else { i + 1 });
+            dlx.up.push(i);
+            dlx.down.push(i);
+            dlx.column.push(i);
+            dlx.row.push(usize::MAX);
+            dlx.size.push(0);
+        }
+
+        for (r, cells) in rows.iter().enumerate() {
+            let mut first: Option<usize> = None;
+            for cell in cells.iter() {
+                let c = cell + 1;
+                let node = dlx.left.len();
+
+                dlx.column.push(c);
+                dlx.row.push(r);
+                dlx.size.push(0);
+
+                let last_up = dlx.up[c];
+                dlx.up.push(last_up);
+                dlx.down.push(c);
+                dlx.down[last_up] = node;
+                dlx.up[c] = node;
+                dlx.size[c] += 1;
+
+                match first {
+                    None => {
+                        dlx.left.push(node);
+                        dlx.right.push(node);
+                        first = Some(node);
+                    }
+                    Some(first) => {
+                        let last = dlx.left[first];
+                        dlx.left.push(last);
+                        dlx.right.push(first);
+                        dlx.right[last] = node;
+                        dlx.left[first] = node;
+                    }
+                }
+            }
+        }
+
+        dlx
+    }
+
+    fn cover(&mut self, c: usize) {
+        let (l, r) = (self.left[c], self.right[c]);
+        self.right[l] = r;
+        self.left[r] = l;
+
+        let mut i = self.down[c];
+        while i != c {
+            let mut j = self.right[i];
+            while j != i {
+                let (u, d) = (self.up[j], self.down[j]);
+                self.down[u] = d;
+                self.up[d] = u;
+                self.size[self.column[j]] -= 1;
+                j = self.right[j];
+            }
+            i = self.down[i];
+        }
+    }
+
+    fn uncover(&mut self, c: usize) {
+        let mut i = self.up[c];
+        while i != c {
+            let mut j = self.left[i];
+            while j != i {
+                self.size[self.column[j]] += 1;
+                let (u, d) = (self.up[j], self.down[j]);
+                self.down[u] = j;
+                self.up[d] = j;
+                j = self.left[j];
+            }
+            i = self.up[i];
+        }
+
+        let (l, r) = (self.left[c], self.right[c]);
+        self.right[l] = c;
+        self.left[r] = c;
+    }
+
+    fn search(
+        &mut self,
+        partial: &mut Vec<usize>,
+        accept: &mut dyn FnMut(&[usize], usize) -> bool,
+        solutions: &mut Vec<Vec<usize>>,
+    ) {
+        if self.right[0] == 0 {
+            solutions.push(partial.clone());
+            return;
+        }
+
+        // the column with the fewest rows left keeps the search small
+        let mut c = self.right[0];
+        let mut j = self.right[c];
+        while j != 0 {
+            if self.size[j] < self.size[c] {
+                c = j;
+            }
+            j = self.right[j];
+        }
+
+        if self.size[c] == 0 {
+            return;
+        }
+
+        self.cover(c);
+
+        let mut r = self.down[c];
+        while r != c {
+            if accept(partial, self.row[r]) {
+                partial.push(self.row[r]);
+
+                let mut j = self.right[r];
+                while j != r {
+                    self.cover(self.column[j]);
+                    j = self.right[j];
+                }
+
+                self.search(partial, accept, solutions);
+
+                let mut j = self.left[r];
+                while j != r {
+                    self.uncover(self.column[j]);
+                    j = self.left[j];
+                }
+
+                partial.pop();
+            }
+            r = self.down[r];
+        }
+
+        self.uncover(c);
+    }
+}